@@ -2,8 +2,9 @@ use anyhow::{anyhow, bail, Context, Result};
 use std::{
     env, fs, io,
     path::{Path, PathBuf},
-    process::{Command, Stdio},
+    process::{self, Command, Stdio},
     str,
+    time::{SystemTime, UNIX_EPOCH},
 };
 use structopt::{clap::AppSettings, StructOpt};
 
@@ -30,6 +31,9 @@ enum Subcommand {
         /// Build artifacts in release mode, with optimizations
         #[structopt(long)]
         release: bool,
+        /// Embed the Wasm modules into the binary instead of putting them next to it
+        #[structopt(long)]
+        embed: bool,
         /// Pass additional arguments directly to cargo build command
         #[structopt()]
         args: Vec<String>,
@@ -39,6 +43,9 @@ enum Subcommand {
         /// Run in release mode, with optimizations
         #[structopt(long)]
         release: bool,
+        /// Embed the Wasm modules into the binary instead of putting them next to it
+        #[structopt(long)]
+        embed: bool,
         /// Pass additional arguments directly to cargo run command
         #[structopt()]
         args: Vec<String>,
@@ -54,8 +61,16 @@ enum Subcommand {
 fn main() {
     let opt = Opt::from_args();
     let res = match opt.cmd {
-        Subcommand::Build { release, args } => build(release, &args),
-        Subcommand::Run { release, args } => run(release, &args),
+        Subcommand::Build {
+            release,
+            embed,
+            args,
+        } => build(release, embed, &args),
+        Subcommand::Run {
+            release,
+            embed,
+            args,
+        } => run(release, embed, &args),
         Subcommand::Clean { args } => clean(&args),
     };
 
@@ -64,7 +79,7 @@ fn main() {
     }
 }
 
-fn build(release: bool, args: &[String]) -> Result<()> {
+fn build(release: bool, embed: bool, args: &[String]) -> Result<()> {
     let profile = if release { "release" } else { "debug" };
     let out_dir = Path::new("target").join(&profile);
 
@@ -100,7 +115,38 @@ fn build(release: bool, args: &[String]) -> Result<()> {
         }
     }
 
-    // Parse manifest of the workspace and extract gfaas deps
+    // Run cargo build, which generates the modules. When they are to be embedded, the project
+    // is only checked at this point, and built once the modules are compiled. The modules and
+    // shared items are marked with the id of the build, which tells the stale ones.
+    let build_id = format!(
+        "{}-{}",
+        SystemTime::now().duration_since(UNIX_EPOCH)?.as_nanos(),
+        process::id()
+    );
+    let mut cmd = Command::new("cargo");
+    cmd.arg(if embed { "check" } else { "build" })
+        // TODO We don't want the user to pass `--release` using aux cargo args,
+        // so let's filter it out for now. In the future, we might want to
+        // throw an error instead.
+        .args(
+            args.iter()
+                .filter(|x| x.as_str() != "--release" && !x.contains("--target-dir")),
+        )
+        .envs(env::vars())
+        .env("CARGO_TARGET_DIR", "target")
+        .env("GFAAS_OUT_DIR", &out_dir)
+        .env("GFAAS_BUILD_ID", &build_id)
+        .env_remove("GFAAS_EMBED_DIR")
+        .stdout(Stdio::inherit())
+        .stderr(Stdio::inherit());
+    if release {
+        cmd.arg("--release");
+    }
+    let _cmd_out = cmd.output().context("failed to build the project")?;
+    prune_stale(&module_path, &build_id)?;
+
+    // Parse manifest of the workspace and extract gfaas deps. This needs to happen after
+    // the project is built, since that's when the modules' metadata gets generated.
     let manifest_path = Path::new(workspace_root).join("Cargo.toml");
     let contents = fs::read_to_string(&manifest_path)
         .with_context(|| format!("failed to read '{}'", manifest_path.display()))?;
@@ -124,15 +170,17 @@ fn build(release: bool, args: &[String]) -> Result<()> {
             serde_json = "1"
         },
     );
+    let gfaas_deps = gfaas_toml
+        .as_table_mut()
+        .unwrap()
+        .get_mut("dependencies")
+        .unwrap()
+        .as_table_mut()
+        .unwrap();
+    for (key, value) in format_dependencies(&module_path)? {
+        gfaas_deps.insert(key, value);
+    }
     if let Some(deps) = manifest_toml.remove("gfaas_dependencies") {
-        let gfaas_deps = gfaas_toml
-            .as_table_mut()
-            .unwrap()
-            .get_mut("dependencies")
-            .unwrap()
-            .as_table_mut()
-            .unwrap();
-
         for key in deps.as_table().unwrap().keys() {
             gfaas_deps.insert(key.to_owned(), deps[key].clone());
         }
@@ -143,25 +191,10 @@ fn build(release: bool, args: &[String]) -> Result<()> {
     fs::write(module_path.join("Cargo.toml"), gfaas_toml)
         .with_context(|| format!("saving '{}'", module_path.join("Cargo.toml").display()))?;
 
-    // Run cargo build
-    let mut cmd = Command::new("cargo");
-    cmd.arg("build")
-        // TODO We don't want the user to pass `--release` using aux cargo args,
-        // so let's filter it out for now. In the future, we might want to
-        // throw an error instead.
-        .args(
-            args.iter()
-                .filter(|x| x.as_str() != "--release" && !x.contains("--target-dir")),
-        )
-        .envs(env::vars())
-        .env("CARGO_TARGET_DIR", "target")
-        .env("GFAAS_OUT_DIR", &out_dir)
-        .stdout(Stdio::inherit())
-        .stderr(Stdio::inherit());
-    if release {
-        cmd.arg("--release");
-    }
-    let _cmd_out = cmd.output().context("failed to build the project")?;
+    // Items shared with the modules end up in the library of gfaas_modules crate.
+    let lib_path = module_path.join("src").join("lib.rs");
+    fs::write(&lib_path, shared_lib(&module_path)?)
+        .with_context(|| format!("saving '{}'", lib_path.display()))?;
 
     // Next, run cargo build --target=wasm32-wasi on gfaas_modules crate.
     let mut cmd = Command::new("cargo");
@@ -176,20 +209,17 @@ fn build(release: bool, args: &[String]) -> Result<()> {
     }
     let _ = cmd.output().context("failed to build the gfaas modules")?;
 
-    // Copy Wasm binaries next to the binary proper
+    // Copy Wasm binaries next to the binary proper. Binaries of the pruned modules may still
+    // be around in the target dir, and so only those of the current modules are copied.
     let from_dir = module_path
         .join("target")
         .join("wasm32-wasi")
         .join(&profile);
     let mut entries = vec![];
-    for entry in fs::read_dir(&from_dir)? {
-        let entry = entry?;
-        let entry_path = entry.path();
-
-        if let Some(ext) = entry_path.extension() {
-            if ext == "wasm" {
-                entries.push(PathBuf::from(entry_path.file_name().unwrap()));
-            }
+    for entry in fs::read_dir(&bin_path)? {
+        let entry_path = entry?.path();
+        if let Some(stem) = entry_path.file_stem() {
+            entries.push(Path::new(stem).with_extension("wasm"));
         }
     }
 
@@ -209,13 +239,166 @@ fn build(release: bool, args: &[String]) -> Result<()> {
         })?;
     }
 
+    if embed {
+        let mut cmd = Command::new("cargo");
+        cmd.arg("build")
+            .args(
+                args.iter()
+                    .filter(|x| x.as_str() != "--release" && !x.contains("--target-dir")),
+            )
+            .envs(env::vars())
+            .env("CARGO_TARGET_DIR", "target")
+            .env("GFAAS_OUT_DIR", &out_dir)
+            .env("GFAAS_BUILD_ID", &build_id)
+            .env("GFAAS_EMBED_DIR", embed_dir(&out_dir)?)
+            .stdout(Stdio::inherit())
+            .stderr(Stdio::inherit());
+        if release {
+            cmd.arg("--release");
+        }
+        let _ = cmd
+            .output()
+            .context("failed to build the project with embedded Wasm modules")?;
+    }
+
     Ok(())
 }
 
-fn run(release: bool, args: &[String]) -> Result<()> {
+/// Directory the Wasm modules are embedded from, which has to be absolute, since
+/// `include_bytes!` resolves relative paths against the source file.
+fn embed_dir(out_dir: &Path) -> Result<PathBuf> {
+    out_dir
+        .canonicalize()
+        .with_context(|| format!("resolving '{}'", out_dir.display()))
+}
+
+/// Collects dependencies required by serialization formats used by the generated modules,
+/// as recorded in their metadata.
+fn format_dependencies(module_path: &Path) -> Result<toml::value::Table> {
+    let mut deps = toml::value::Table::new();
+    let meta_dir = module_path.join("meta");
+    if !meta_dir.is_dir() {
+        return Ok(deps);
+    }
+    for entry in fs::read_dir(&meta_dir)? {
+        let entry_path = entry?.path();
+        let contents = fs::read(&entry_path)
+            .with_context(|| format!("failed to read '{}'", entry_path.display()))?;
+        let meta: serde_json::Value = serde_json::from_slice(&contents)
+            .with_context(|| format!("parsing '{}' as JSON", entry_path.display()))?;
+        let (name, version) = match meta["format"].as_str() {
+            Some("bincode") => ("bincode", "1"),
+            Some("cbor") => ("serde_cbor", "0.11"),
+            Some("msgpack") => ("rmp-serde", "1"),
+            _ => continue,
+        };
+        deps.insert(name.to_owned(), toml::Value::String(version.to_owned()));
+    }
+    Ok(deps)
+}
+
+/// Removes the modules and shared items left over from the previous builds of the crates which
+/// were rebuilt by build `build_id`. Module names change along with their contents, while each
+/// build of a crate writes all of its modules and shared items anew, marked with the id of the
+/// build, and so anything of such a crate marked otherwise is stale.
+fn prune_stale(module_path: &Path, build_id: &str) -> Result<()> {
+    // Each entry is the crate it comes from, the build which last wrote it, and its files.
+    let mut entries: Vec<(String, String, Vec<PathBuf>)> = vec![];
+    let meta_dir = module_path.join("meta");
+    if meta_dir.is_dir() {
+        for entry in fs::read_dir(&meta_dir)? {
+            let entry_path = entry?.path();
+            let contents = fs::read(&entry_path)
+                .with_context(|| format!("failed to read '{}'", entry_path.display()))?;
+            let meta: serde_json::Value = serde_json::from_slice(&contents)
+                .with_context(|| format!("parsing '{}' as JSON", entry_path.display()))?;
+            let krate = meta["crate"].as_str().unwrap_or_default().to_owned();
+            let build = meta["build"].as_str().unwrap_or_default().to_owned();
+            let stem = entry_path
+                .file_stem()
+                .ok_or(anyhow!("malformed module metadata path"))?;
+            let bin_path = module_path
+                .join("src")
+                .join("bin")
+                .join(Path::new(stem).with_extension("rs"));
+            entries.push((krate, build, vec![entry_path, bin_path]));
+        }
+    }
+    let shared_dir = module_path.join("src").join("shared");
+    if shared_dir.is_dir() {
+        for crate_entry in fs::read_dir(&shared_dir)? {
+            let crate_entry = crate_entry?;
+            let krate = crate_entry
+                .file_name()
+                .into_string()
+                .map_err(|_| anyhow!("shared item dir name is not a UTF8 string"))?;
+            for entry in fs::read_dir(crate_entry.path())? {
+                let entry_path = entry?.path();
+                let contents = fs::read_to_string(&entry_path)
+                    .with_context(|| format!("failed to read '{}'", entry_path.display()))?;
+                // Shared items lead with a `// gfaas-build <id>` line.
+                let build = contents
+                    .lines()
+                    .next()
+                    .and_then(|line| line.strip_prefix("// gfaas-build "))
+                    .unwrap_or_default()
+                    .to_owned();
+                entries.push((krate.clone(), build, vec![entry_path]));
+            }
+        }
+    }
+
+    let rebuilt: Vec<_> = entries
+        .iter()
+        .filter(|(_, build, _)| build == build_id)
+        .map(|(krate, _, _)| krate.clone())
+        .collect();
+    for (krate, build, paths) in entries {
+        if build != build_id && rebuilt.contains(&krate) {
+            for path in paths {
+                if let Err(err) = fs::remove_file(&path) {
+                    if err.kind() != io::ErrorKind::NotFound {
+                        return Err(err)
+                            .with_context(|| format!("removing stale '{}'", path.display()));
+                    }
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Assembles lib.rs of gfaas_modules crate out of the items shared with the modules, as
+/// recorded by `gfaas::shared`.
+fn shared_lib(module_path: &Path) -> Result<String> {
+    let mut lib = String::new();
+    let shared_dir = module_path.join("src").join("shared");
+    if !shared_dir.is_dir() {
+        return Ok(lib);
+    }
+    // Items are grouped by the crate they come from.
+    let mut entries = vec![];
+    for crate_entry in fs::read_dir(&shared_dir)? {
+        let crate_entry = crate_entry?;
+        for entry in fs::read_dir(crate_entry.path())? {
+            entries.push((crate_entry.file_name(), entry?.file_name()));
+        }
+    }
+    entries.sort();
+    for (krate, entry) in entries {
+        let (krate, entry) = krate
+            .to_str()
+            .zip(entry.to_str())
+            .ok_or(anyhow!("shared item file name is not a UTF8 string"))?;
+        lib.push_str(&format!("include!(\"shared/{}/{}\");\n", krate, entry));
+    }
+    Ok(lib)
+}
+
+fn run(release: bool, embed: bool, args: &[String]) -> Result<()> {
     // We need to run cargo build first so that the Wasm artifacts are properly
     // generated.
-    build(release, &[])?;
+    build(release, embed, &[])?;
 
     // Run cargo run
     let mut cmd = Command::new("cargo");
@@ -231,6 +414,14 @@ fn run(release: bool, args: &[String]) -> Result<()> {
         .env("CARGO_TARGET_DIR", "target")
         .stdout(Stdio::inherit())
         .stderr(Stdio::inherit());
+    // Switching embedding off would have cargo rebuild the project without the modules.
+    if embed {
+        let profile = if release { "release" } else { "debug" };
+        let out_dir = Path::new("target").join(profile);
+        cmd.env("GFAAS_EMBED_DIR", embed_dir(&out_dir)?);
+    } else {
+        cmd.env_remove("GFAAS_EMBED_DIR");
+    }
     if release {
         cmd.arg("--release");
     }
@@ -8,7 +8,39 @@ use syn::parse_macro_input;
 #[proc_macro_attribute]
 pub fn remote_fn(attr: TokenStream, item: TokenStream) -> TokenStream {
     let attrs = parse_macro_input!(attr as logic::GwasmAttrs);
-    let preserved = item.clone();
-    let f = parse_macro_input!(item as logic::GwasmFn);
-    logic::remote_fn_impl(attrs, f, preserved.into()).into()
+    if let Ok(item) = syn::parse::<syn::ItemImpl>(item.clone()) {
+        return logic::remote_impl(attrs, item)
+            .unwrap_or_else(|err| err.to_compile_error())
+            .into();
+    }
+    let f = parse_macro_input!(item as syn::ItemFn);
+    logic::remote_fn_impl(attrs, f)
+        .unwrap_or_else(|err| err.to_compile_error())
+        .into()
+}
+
+#[proc_macro_attribute]
+pub fn shared(attr: TokenStream, item: TokenStream) -> TokenStream {
+    let item = parse_macro_input!(item as syn::Item);
+    logic::shared_impl(attr.into(), item)
+        .unwrap_or_else(|err| err.to_compile_error())
+        .into()
+}
+
+#[doc(hidden)]
+#[proc_macro]
+pub fn wasm_module(input: TokenStream) -> TokenStream {
+    let module = parse_macro_input!(input as logic::WasmModule);
+    logic::wasm_module_impl(module)
+        .unwrap_or_else(|err| err.to_compile_error())
+        .into()
+}
+
+#[doc(hidden)]
+#[proc_macro]
+pub fn shared_item(input: TokenStream) -> TokenStream {
+    let item = parse_macro_input!(input as logic::SharedItem);
+    logic::shared_item_impl(item)
+        .unwrap_or_else(|err| err.to_compile_error())
+        .into()
 }
@@ -1,66 +1,153 @@
-use proc_macro2::TokenStream;
-use quote::{format_ident, quote};
-use std::{collections::VecDeque, env, fs::File, io::Write, path::Path};
+use proc_macro2::{Group, Span, TokenStream, TokenTree};
+use quote::{format_ident, quote, quote_spanned, ToTokens};
+use sha2::{Digest, Sha256};
+use std::{
+    convert::TryFrom,
+    env, fs,
+    path::{Component, Path, PathBuf},
+};
 use syn::{
     parenthesized,
     parse::{Parse, ParseStream},
     punctuated::Punctuated,
     token::Paren,
-    Block, ExprLit, FnArg, Ident, Lit, Pat, ReturnType, Token, Type, Visibility,
+    Attribute, Binding, ExprLit, FnArg, GenericArgument, GenericParam, Generics, Ident, ImplItem,
+    ImplItemMethod, Item, ItemFn, ItemImpl, Lit, LitBool, LitFloat, LitInt, LitStr, Pat,
+    PathArguments, ReturnType, Token, Type, Visibility,
 };
 
-#[derive(Debug)]
-pub struct GwasmFn {
-    vis: Visibility,
-    fn_token: Token![fn],
-    ident: Ident,
-    paren_token: Paren,
-    args: Punctuated<FnArg, Token![,]>,
-    ret: ReturnType,
-    body: Box<Block>,
-}
-
-impl Parse for GwasmFn {
-    fn parse(input: ParseStream) -> syn::Result<Self> {
-        let content;
-        Ok(GwasmFn {
-            vis: input.parse()?,
-            fn_token: input.parse()?,
-            ident: input.parse()?,
-            paren_token: parenthesized!(content in input),
-            args: content.parse_terminated(FnArg::parse)?,
-            ret: input.parse()?,
-            body: input.parse()?,
-        })
-    }
-}
-
-fn validate_extract_args(input: impl IntoIterator<Item = FnArg>) -> Vec<(Box<Pat>, Box<Type>)> {
+fn validate_extract_args(
+    input: impl IntoIterator<Item = FnArg>,
+    in_impl: bool,
+) -> syn::Result<Vec<(Box<Pat>, Box<Type>)>> {
     let mut args = vec![];
     for arg in input {
         let (pat, ty) = match arg {
             FnArg::Typed(arg) => {
-                if arg.attrs.len() > 0 {
-                    panic!("attributes around function arguments are unsupported");
+                if let Some(attr) = arg.attrs.first() {
+                    return Err(syn::Error::new_spanned(
+                        attr,
+                        "attributes around function arguments are unsupported",
+                    ));
                 }
                 let pat = arg.pat;
                 let ty = arg.ty;
                 (pat, ty)
             }
-            _ => panic!("functions taking 'self' are unsupported"),
+            // The receiver is passed just like any other argument, and so methods can take
+            // it by value or by shared reference only.
+            FnArg::Receiver(recv) => {
+                if !in_impl {
+                    return Err(syn::Error::new_spanned(
+                        recv,
+                        "methods taking 'self' require 'remote_fn' attribute on the impl block too",
+                    ));
+                }
+                let pat = syn::parse_quote!(self);
+                let ty = match &recv.reference {
+                    Some(_) if recv.mutability.is_some() => {
+                        return Err(syn::Error::new_spanned(
+                            recv,
+                            "methods taking '&mut self' are unsupported",
+                        ))
+                    }
+                    Some(_) => syn::parse_quote!(&Self),
+                    None => syn::parse_quote!(Self),
+                };
+                (pat, ty)
+            }
         };
         args.push((pat, ty));
     }
-    args
+    Ok(args)
+}
+
+/// Argument of the annotated function.
+struct Arg {
+    /// Identifier the argument is bound to in the expanded code. This is the identifier of
+    /// the argument if it's bound to one, or a synthetic one otherwise.
+    ident: Ident,
+    /// Declared type of the argument.
+    ty: Box<Type>,
+    /// Owned type the argument is deserialized into before passing it to the function.
+    owned: Type,
+    /// Whether the argument is passed to the function by reference.
+    by_ref: bool,
+}
+
+impl Arg {
+    fn new(index: usize, pat: &Pat, ty: Box<Type>) -> syn::Result<Self> {
+        let ident = match pat {
+            Pat::Ident(pat) if pat.by_ref.is_none() && pat.subpat.is_none() => pat.ident.clone(),
+            _ => format_ident!("__gfaas_arg{}", index),
+        };
+        let (owned, by_ref) = match &*ty {
+            Type::Reference(r) => {
+                if let Some(mutability) = &r.mutability {
+                    return Err(syn::Error::new_spanned(
+                        mutability,
+                        "arguments passed by mutable reference are unsupported",
+                    ));
+                }
+                let owned = match &*r.elem {
+                    Type::Path(path) if path.qself.is_none() && path.path.is_ident("str") => {
+                        syn::parse_quote!(String)
+                    }
+                    Type::Slice(slice) => {
+                        let elem = &slice.elem;
+                        syn::parse_quote!(Vec<#elem>)
+                    }
+                    elem => elem.clone(),
+                };
+                (owned, true)
+            }
+            ty => (ty.clone(), false),
+        };
+        Ok(Self {
+            ident,
+            ty,
+            owned,
+            by_ref,
+        })
+    }
+
+    /// Expression passing the owned value bound to `ident` to the function.
+    fn pass(&self, ident: &Ident) -> TokenStream {
+        if self.by_ref {
+            quote!(&#ident)
+        } else {
+            quote!(#ident)
+        }
+    }
 }
 
-fn validate_extract_return_type(output: &ReturnType) -> Box<Type> {
-    match output {
-        ReturnType::Default => panic!("functions returning unit type () are unsupported"),
+fn extract_return_type(ret: &ReturnType) -> Box<Type> {
+    match ret {
+        ReturnType::Default => Box::new(syn::parse_quote!(())),
         ReturnType::Type(_, tt) => tt.clone(),
     }
 }
 
+/// Extracts `T` and `E` if `ty` is `Result<T, E>`.
+fn extract_result_types(ty: &Type) -> Option<(&Type, &Type)> {
+    let path = match ty {
+        Type::Path(path) if path.qself.is_none() => &path.path,
+        _ => return None,
+    };
+    let segment = path.segments.last()?;
+    if segment.ident != "Result" {
+        return None;
+    }
+    let args = match &segment.arguments {
+        PathArguments::AngleBracketed(args) if args.args.len() == 2 => &args.args,
+        _ => return None,
+    };
+    match (&args[0], &args[1]) {
+        (GenericArgument::Type(ok), GenericArgument::Type(err)) => Some((ok, err)),
+        _ => None,
+    }
+}
+
 #[derive(Debug)]
 pub struct GwasmAttr {
     ident: Ident,
@@ -78,304 +165,1540 @@ impl Parse for GwasmAttr {
     }
 }
 
+impl GwasmAttr {
+    fn parse_bool(&self) -> syn::Result<bool> {
+        match &self.value.lit {
+            Lit::Str(s) => s.value().parse().map_err(|_| {
+                syn::Error::new_spanned(
+                    s,
+                    format!(
+                        "invalid value for '{}': expected 'true' or 'false'",
+                        self.ident
+                    ),
+                )
+            }),
+            Lit::Bool(b) => Ok(b.value),
+            x => Err(syn::Error::new_spanned(
+                x,
+                format!(
+                    "invalid value for '{}': expected string or bool",
+                    self.ident
+                ),
+            )),
+        }
+    }
+
+    fn parse_int(&self) -> syn::Result<u64> {
+        match &self.value.lit {
+            Lit::Str(s) => s.value().parse().map_err(|err| {
+                syn::Error::new_spanned(s, format!("invalid value for '{}': {}", self.ident, err))
+            }),
+            Lit::Int(i) => i.base10_parse(),
+            x => Err(syn::Error::new_spanned(
+                x,
+                format!("invalid value for '{}': expected string or int", self.ident),
+            )),
+        }
+    }
+
+    fn parse_float(&self) -> syn::Result<f64> {
+        match &self.value.lit {
+            Lit::Str(s) => s.value().parse().map_err(|err| {
+                syn::Error::new_spanned(s, format!("invalid value for '{}': {}", self.ident, err))
+            }),
+            Lit::Int(i) => i.base10_parse(),
+            Lit::Float(f) => f.base10_parse(),
+            x => Err(syn::Error::new_spanned(
+                x,
+                format!(
+                    "invalid value for '{}': expected string, int or float",
+                    self.ident
+                ),
+            )),
+        }
+    }
+
+    fn parse_str(&self) -> syn::Result<String> {
+        match &self.value.lit {
+            Lit::Str(s) => Ok(s.value()),
+            x => Err(syn::Error::new_spanned(
+                x,
+                format!("invalid value for '{}': expected string", self.ident),
+            )),
+        }
+    }
+}
+
+/// Single instantiation of a generic function, binding each of its type parameters, e.g.
+/// `T = u64`, or `(T = u64, U = String)` for functions with multiple type parameters.
+#[derive(Debug)]
+struct Instantiation {
+    paren_token: Option<Paren>,
+    bindings: Punctuated<Binding, Token![,]>,
+}
+
+impl Parse for Instantiation {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        if input.peek(Paren) {
+            let content;
+            Ok(Instantiation {
+                paren_token: Some(parenthesized!(content in input)),
+                bindings: content.parse_terminated(Binding::parse)?,
+            })
+        } else {
+            let mut bindings = Punctuated::new();
+            bindings.push(input.parse()?);
+            Ok(Instantiation {
+                paren_token: None,
+                bindings,
+            })
+        }
+    }
+}
+
+/// `instantiate(..)` attribute listing the instantiations of a generic function.
+#[derive(Debug)]
+pub struct InstantiateAttr {
+    ident: Ident,
+    instantiations: Punctuated<Instantiation, Token![,]>,
+}
+
+impl Parse for InstantiateAttr {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let content;
+        let ident: Ident = input.parse()?;
+        if ident != "instantiate" {
+            return Err(unexpected_attr(&ident));
+        }
+        parenthesized!(content in input);
+        Ok(InstantiateAttr {
+            ident,
+            instantiations: content.parse_terminated(Instantiation::parse)?,
+        })
+    }
+}
+
 #[derive(Debug)]
-pub struct GwasmAttrs(Punctuated<GwasmAttr, Token![,]>);
+pub struct GwasmAttrs {
+    attrs: Vec<GwasmAttr>,
+    instantiate: Option<InstantiateAttr>,
+}
 
 impl Parse for GwasmAttrs {
     fn parse(input: ParseStream) -> syn::Result<Self> {
-        Ok(GwasmAttrs(input.parse_terminated(GwasmAttr::parse)?))
+        let mut attrs = vec![];
+        let mut instantiate = None;
+        while !input.is_empty() {
+            if input.peek(Ident) && input.peek2(Paren) {
+                let attr: InstantiateAttr = input.parse()?;
+                if instantiate.is_some() {
+                    return Err(syn::Error::new_spanned(
+                        &attr.ident,
+                        format!("duplicate attribute '{}'", attr.ident),
+                    ));
+                }
+                instantiate = Some(attr);
+            } else {
+                attrs.push(input.parse()?);
+            }
+            if input.is_empty() {
+                break;
+            }
+            input.parse::<Token![,]>()?;
+        }
+        Ok(GwasmAttrs { attrs, instantiate })
     }
 }
 
+/// Serialization format of inputs and output, mirroring `gfaas::Format`.
+#[derive(Debug, Clone, Copy)]
+enum SerdeFormat {
+    Json,
+    Bincode,
+    Cbor,
+    Msgpack,
+}
+
+impl SerdeFormat {
+    fn from_attr(attr: &GwasmAttr) -> syn::Result<Self> {
+        match attr.parse_str()?.as_str() {
+            "json" => Ok(Self::Json),
+            "bincode" => Ok(Self::Bincode),
+            "cbor" => Ok(Self::Cbor),
+            "msgpack" => Ok(Self::Msgpack),
+            _ => Err(syn::Error::new_spanned(
+                &attr.value,
+                "invalid value for 'format': expected 'json', 'bincode', 'cbor', or 'msgpack'",
+            )),
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Self::Json => "json",
+            Self::Bincode => "bincode",
+            Self::Cbor => "cbor",
+            Self::Msgpack => "msgpack",
+        }
+    }
+
+    /// Host-side `gfaas::Format` variant.
+    fn variant(self) -> TokenStream {
+        match self {
+            Self::Json => quote!(gfaas::Format::Json),
+            Self::Bincode => quote!(gfaas::Format::Bincode),
+            Self::Cbor => quote!(gfaas::Format::Cbor),
+            Self::Msgpack => quote!(gfaas::Format::Msgpack),
+        }
+    }
+
+    /// Wasm-side expression deserializing `data` of type `&[u8]`.
+    fn deserialize(self, data: &Ident) -> TokenStream {
+        match self {
+            Self::Json => quote!(serde_json::from_slice(#data)),
+            Self::Bincode => quote!(bincode::deserialize(#data)),
+            Self::Cbor => quote!(serde_cbor::from_slice(#data)),
+            Self::Msgpack => quote!(rmp_serde::from_slice(#data)),
+        }
+    }
+
+    /// Wasm-side expression serializing `value`.
+    fn serialize(self, value: &Ident) -> TokenStream {
+        match self {
+            Self::Json => quote!(serde_json::to_vec(&#value)),
+            Self::Bincode => quote!(bincode::serialize(&#value)),
+            Self::Cbor => quote!(serde_cbor::to_vec(&#value)),
+            Self::Msgpack => quote!(rmp_serde::to_vec_named(&#value)),
+        }
+    }
+}
+
+/// Custom constraint parsed from `constraints` attribute.
+#[derive(Debug)]
+struct RawConstraint {
+    key: String,
+    op: &'static str,
+    value: Lit,
+}
+
+impl RawConstraint {
+    const OPS: &'static [&'static str] = &["==", "!=", "<", ">"];
+
+    /// Parses comma-separated constraints of the form `key op value`, where `op` is one of
+    /// `==`, `!=`, `<` or `>`, and `value` is a number, a bool or a (possibly quoted) string.
+    /// Commas within quoted strings don't separate constraints.
+    fn from_attr(attr: &GwasmAttr) -> syn::Result<Vec<Self>> {
+        let raw = attr.parse_str()?;
+        let span = attr.value.lit.span();
+        let invalid = |msg: String| {
+            syn::Error::new(span, format!("invalid value for 'constraints': {}", msg))
+        };
+
+        let mut constraints = vec![];
+        for entry in Self::split(&raw).map_err(invalid)? {
+            // Property names consist of alphanumerics, dots, underscores and dashes only, so
+            // the operator starts at the first character any of the operators is made of.
+            let pos = entry.find(&['=', '!', '<', '>'][..]).unwrap_or(entry.len());
+            let rest = &entry[pos..];
+            if rest.starts_with(">=") || rest.starts_with("<=") {
+                return Err(invalid(format!(
+                    "'{}' is unsupported, expected one of '==', '!=', '<', '>', found '{}'",
+                    &rest[..2],
+                    entry
+                )));
+            }
+            let op = Self::OPS
+                .iter()
+                .copied()
+                .find(|op| rest.starts_with(op))
+                .ok_or_else(|| {
+                    invalid(format!(
+                        "expected 'key op value' with op one of '==', '!=', '<', '>', found '{}'",
+                        entry
+                    ))
+                })?;
+            let key = entry[..pos].trim();
+            let value = entry[pos + op.len()..].trim();
+            if key.is_empty()
+                || !key
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '.' || c == '_' || c == '-')
+            {
+                return Err(invalid(format!("invalid property name '{}'", key)));
+            }
+            if value.is_empty() {
+                return Err(invalid(format!("missing value for '{}'", key)));
+            }
+            let value = if let Ok(i) = value.parse::<i64>() {
+                Lit::Int(LitInt::new(&i.to_string(), span))
+            } else if let Ok(f) = value.parse::<f64>() {
+                if !f.is_finite() {
+                    return Err(invalid(format!(
+                        "'{}' is not a finite number, quote it to compare '{}' with a string",
+                        value, key
+                    )));
+                }
+                Lit::Float(LitFloat::new(&format!("{:?}", f), span))
+            } else if let Ok(b) = value.parse::<bool>() {
+                Lit::Bool(LitBool { value: b, span })
+            } else {
+                let unquoted = value
+                    .strip_prefix('"')
+                    .and_then(|x| x.strip_suffix('"'))
+                    .unwrap_or(value);
+                Lit::Str(LitStr::new(unquoted, span))
+            };
+            constraints.push(Self {
+                key: key.to_owned(),
+                op,
+                value,
+            });
+        }
+        Ok(constraints)
+    }
+
+    /// Splits `raw` at the commas outside of double quotes into trimmed, non-empty entries.
+    fn split(raw: &str) -> Result<Vec<&str>, String> {
+        let mut entries = vec![];
+        let mut start = 0;
+        let mut quoted = false;
+        for (pos, c) in raw.char_indices() {
+            match c {
+                '"' => quoted = !quoted,
+                ',' if !quoted => {
+                    entries.push(&raw[start..pos]);
+                    start = pos + 1;
+                }
+                _ => {}
+            }
+        }
+        if quoted {
+            return Err(format!("unterminated quoted string in '{}'", raw));
+        }
+        entries.push(&raw[start..]);
+        Ok(entries
+            .into_iter()
+            .map(str::trim)
+            .filter(|x| !x.is_empty())
+            .collect())
+    }
+
+    /// Host-side call adding the constraint to `gfaas::Invocation`.
+    fn to_tokens(&self) -> TokenStream {
+        let key = &self.key;
+        let value = &self.value;
+        let op = match self.op {
+            "==" => quote!(gfaas::ConstraintOp::Equal),
+            "!=" => quote!(gfaas::ConstraintOp::NotEqual),
+            "<" => quote!(gfaas::ConstraintOp::LessThan),
+            _ => quote!(gfaas::ConstraintOp::GreaterThan),
+        };
+        quote!(.with_constraint(#key, #op, #value))
+    }
+}
+
+const ATTRS: &[&str] = &[
+    "run_local",
+    "budget",
+    "timeout",
+    "subnet",
+    "format",
+    "retries",
+    "backoff",
+    "min_mem_gib",
+    "min_storage_gib",
+    "min_cpu_threads",
+    "constraints",
+    "datadir",
+    "app_key",
+    "api_url",
+    "instantiate",
+];
+
 #[derive(Debug, Default)]
 struct GwasmParams {
     run_local: Option<bool>,
     budget: Option<u64>,
     timeout: Option<u64>, // In seconds. TODO figure out a more user-friendly alts.
     subnet: Option<String>,
+    format: Option<SerdeFormat>,
+    retries: Option<u32>,
+    backoff: Option<u64>, // In seconds.
+    min_mem_gib: Option<f64>,
+    min_storage_gib: Option<f64>,
+    min_cpu_threads: Option<u32>,
+    constraints: Option<Vec<RawConstraint>>,
+    datadir: Option<String>,
+    app_key: Option<String>,
+    api_url: Option<String>,
 }
 
-pub(super) fn remote_fn_impl(attrs: GwasmAttrs, f: GwasmFn, preserved: TokenStream) -> TokenStream {
-    // Parse attributes
-    let mut params = GwasmParams::default();
-    for attr in attrs.0.into_iter() {
-        let attr_str = attr.ident.to_string();
-        match attr_str.as_str() {
-            "run_local" => {
-                let lit = attr.value.lit;
-                match lit {
-                    Lit::Str(s) => params
-                        .run_local
-                        .replace(s.value().parse().expect("correct value")),
-                    Lit::Bool(b) => params.run_local.replace(b.value),
-                    x => panic!(
-                        "invalid attribute value '{:#?}': expected string or bool",
-                        x
-                    ),
-                };
+impl GwasmParams {
+    fn from_attrs(attrs: Vec<GwasmAttr>) -> syn::Result<Self> {
+        let mut params = Self::default();
+        for attr in attrs {
+            let attr_str = attr.ident.to_string();
+            match attr_str.as_str() {
+                "run_local" => set_once(&mut params.run_local, &attr, attr.parse_bool()?)?,
+                "budget" => set_once(&mut params.budget, &attr, attr.parse_int()?)?,
+                "timeout" => set_once(&mut params.timeout, &attr, attr.parse_int()?)?,
+                "subnet" => set_once(&mut params.subnet, &attr, attr.parse_str()?)?,
+                "format" => set_once(&mut params.format, &attr, SerdeFormat::from_attr(&attr)?)?,
+                "retries" => {
+                    let retries = u32::try_from(attr.parse_int()?).map_err(|err| {
+                        syn::Error::new_spanned(
+                            &attr.value,
+                            format!("invalid value for 'retries': {}", err),
+                        )
+                    })?;
+                    set_once(&mut params.retries, &attr, retries)?
+                }
+                "backoff" => set_once(&mut params.backoff, &attr, attr.parse_int()?)?,
+                "min_mem_gib" | "min_storage_gib" => {
+                    let gib = attr.parse_float()?;
+                    if gib <= 0.0 || !gib.is_finite() {
+                        return Err(syn::Error::new_spanned(
+                            &attr.value,
+                            format!("invalid value for '{}': expected positive number", attr_str),
+                        ));
+                    }
+                    let param = if attr_str == "min_mem_gib" {
+                        &mut params.min_mem_gib
+                    } else {
+                        &mut params.min_storage_gib
+                    };
+                    set_once(param, &attr, gib)?
+                }
+                "min_cpu_threads" => {
+                    let threads = u32::try_from(attr.parse_int()?)
+                        .ok()
+                        .filter(|&threads| threads > 0)
+                        .ok_or_else(|| {
+                            syn::Error::new_spanned(
+                                &attr.value,
+                                "invalid value for 'min_cpu_threads': expected positive int",
+                            )
+                        })?;
+                    set_once(&mut params.min_cpu_threads, &attr, threads)?
+                }
+                "datadir" => set_once(&mut params.datadir, &attr, attr.parse_str()?)?,
+                "app_key" => set_once(&mut params.app_key, &attr, attr.parse_str()?)?,
+                "api_url" => set_once(&mut params.api_url, &attr, attr.parse_str()?)?,
+                "constraints" => set_once(
+                    &mut params.constraints,
+                    &attr,
+                    RawConstraint::from_attr(&attr)?,
+                )?,
+                _ => return Err(unexpected_attr(&attr.ident)),
             }
-            "budget" => {
-                let lit = attr.value.lit;
-                match lit {
-                    Lit::Str(s) => params
-                        .budget
-                        .replace(s.value().parse().expect("correct value")),
-                    Lit::Int(i) => params
-                        .budget
-                        .replace(i.base10_parse().expect("correct value")),
-                    x => panic!("invalid attribute value '{:#?}': expected string or int", x),
-                };
+        }
+        Ok(params)
+    }
+}
+
+fn unexpected_attr(ident: &Ident) -> syn::Error {
+    let expected: Vec<_> = ATTRS.iter().map(|x| format!("'{}'", x)).collect();
+    syn::Error::new_spanned(
+        ident,
+        format!(
+            "unexpected attribute '{}': expected one of {}",
+            ident,
+            expected.join(", ")
+        ),
+    )
+}
+
+/// Wasm module compiled from the annotated function.
+struct Module {
+    /// Name of the module, which is the name of the crate, the module path and the function,
+    /// suffixed with the types of the instantiation for generic functions and a hash of the
+    /// module.
+    name: String,
+    /// Types the type parameters of the function are instantiated with, in the order of
+    /// the parameters.
+    types: Vec<Type>,
+}
+
+/// Resolves the modules compiled from the function: a single one for non-generic functions,
+/// and one per instantiation listed in `instantiate` attribute for generic ones.
+fn resolve_modules(
+    name: &str,
+    type_params: &[Ident],
+    generics: &Generics,
+    instantiate: Option<InstantiateAttr>,
+) -> syn::Result<Vec<Module>> {
+    let instantiate = match instantiate {
+        Some(attr) if type_params.is_empty() => {
+            return Err(syn::Error::new_spanned(
+                &attr.ident,
+                "'instantiate' is only supported on generic functions",
+            ))
+        }
+        Some(attr) => attr,
+        None if type_params.is_empty() => {
+            return Ok(vec![Module {
+                name: name.to_owned(),
+                types: vec![],
+            }])
+        }
+        None => {
+            return Err(syn::Error::new_spanned(
+                generics,
+                "generic functions require 'instantiate' attribute listing the types to instantiate them with",
+            ))
+        }
+    };
+    if instantiate.instantiations.is_empty() {
+        return Err(syn::Error::new_spanned(
+            &instantiate.ident,
+            "invalid value for 'instantiate': expected at least one instantiation",
+        ));
+    }
+
+    let mut modules: Vec<Module> = vec![];
+    for instantiation in instantiate.instantiations {
+        let mut types = vec![None; type_params.len()];
+        for binding in &instantiation.bindings {
+            let pos = type_params
+                .iter()
+                .position(|param| param == &binding.ident)
+                .ok_or_else(|| {
+                    syn::Error::new_spanned(
+                        &binding.ident,
+                        format!("unknown type parameter '{}'", binding.ident),
+                    )
+                })?;
+            if types[pos].replace(binding.ty.clone()).is_some() {
+                return Err(syn::Error::new_spanned(
+                    &binding.ident,
+                    format!("duplicate type parameter '{}'", binding.ident),
+                ));
             }
-            "timeout" => {
-                let lit = attr.value.lit;
-                match lit {
-                    Lit::Str(s) => params
-                        .timeout
-                        .replace(s.value().parse().expect("correct value")),
-                    Lit::Int(i) => params
-                        .timeout
-                        .replace(i.base10_parse().expect("correct value")),
-                    x => panic!("invalid attribute value '{:#?}': expected string or int", x),
-                };
+        }
+        let span = match (instantiation.paren_token, instantiation.bindings.first()) {
+            (Some(paren), _) => paren.span,
+            (None, Some(binding)) => binding.ident.span(),
+            (None, None) => instantiate.ident.span(),
+        };
+        let types = types
+            .into_iter()
+            .zip(type_params)
+            .map(|(ty, param)| {
+                ty.ok_or_else(|| {
+                    syn::Error::new(span, format!("missing type for parameter '{}'", param))
+                })
+            })
+            .collect::<syn::Result<Vec<_>>>()?;
+
+        let mut name = name.to_owned();
+        for ty in &types {
+            push_words(&mut name, ty);
+        }
+        if modules.iter().any(|module| module.name == name) {
+            return Err(syn::Error::new(span, "duplicate instantiation"));
+        }
+        modules.push(Module { name, types });
+    }
+    Ok(modules)
+}
+
+/// Appends `tokens` to module `name`. Module names double as names of the binaries in
+/// gfaas_modules crate, so the tokens are reduced to lowercase alphanumeric words, e.g.
+/// `Vec<u8>` to `vec_u8`.
+fn push_words(name: &mut String, tokens: impl ToTokens) {
+    let tokens = tokens.into_token_stream().to_string().to_lowercase();
+    for word in tokens.split(|c: char| !c.is_ascii_alphanumeric()) {
+        if !word.is_empty() {
+            name.push('_');
+            name.push_str(word);
+        }
+    }
+}
+
+fn set_once<T>(param: &mut Option<T>, attr: &GwasmAttr, value: T) -> syn::Result<()> {
+    if param.replace(value).is_some() {
+        return Err(syn::Error::new_spanned(
+            &attr.ident,
+            format!("duplicate attribute '{}'", attr.ident),
+        ));
+    }
+    Ok(())
+}
+
+/// Name of the crate being compiled, as set by cargo.
+fn crate_name() -> String {
+    env::var("CARGO_CRATE_NAME").unwrap_or_default()
+}
+
+/// Path of the module being expanded within its crate, as words derived from the path of its
+/// source file relative to `src`, e.g. `_fractal_mandel` for `src/fractal/mandel.rs`. Inline
+/// modules aren't reflected in it, which the hash suffixing generated names makes up for.
+fn module_path() -> String {
+    let file = PathBuf::from(proc_macro::Span::call_site().file());
+    let file = match (file.is_relative(), env::current_dir()) {
+        (true, Ok(dir)) => dir.join(file),
+        _ => file,
+    };
+    let file = env::var("CARGO_MANIFEST_DIR")
+        .ok()
+        .and_then(|dir| file.strip_prefix(dir).ok().map(Path::to_owned))
+        .unwrap_or(file);
+    let mut words: Vec<_> = file
+        .with_extension("")
+        .components()
+        .filter_map(|component| match component {
+            Component::Normal(word) => word.to_str().map(str::to_owned),
+            _ => None,
+        })
+        .collect();
+    if words.first().map(String::as_str) == Some("src") {
+        words.remove(0);
+    }
+    if let Some("lib") | Some("main") | Some("mod") = words.last().map(String::as_str) {
+        words.pop();
+    }
+    let mut path = String::new();
+    for word in words {
+        push_words(&mut path, word);
+    }
+    path
+}
+
+/// Hash of `parts` suffixing generated names, as the leading digits of their SHA-256, which
+/// unlike `DefaultHasher` is stable across Rust releases.
+fn stable_hash(parts: &[&str]) -> String {
+    let mut hasher = Sha256::new();
+    // Lengths delimit the parts, so that they can't run into each other.
+    for part in parts {
+        hasher.input((part.len() as u64).to_le_bytes());
+        hasher.input(part);
+    }
+    format!("{:x}", hasher.result())[..16].to_owned()
+}
+
+/// Id of the current build by gfaas tool, which tells the files it generated from the ones
+/// left over from the previous builds.
+fn build_id() -> String {
+    env::var("GFAAS_BUILD_ID").unwrap_or_default()
+}
+
+pub(super) fn remote_fn_impl(attrs: GwasmAttrs, f: ItemFn) -> syn::Result<TokenStream> {
+    expand(attrs, f, None)
+}
+
+/// Impl block annotated with `remote_fn`, whose annotated methods are expanded in place.
+struct ImplContext {
+    self_ty: Box<Type>,
+    /// Methods of the impl block as written, without `remote_fn` attributes and visibility.
+    /// These are copied into the Wasm modules of the annotated ones, so that they can call
+    /// each other.
+    methods: Vec<ImplItemMethod>,
+}
+
+impl ImplContext {
+    /// Declares trait `ident` with `methods`, and implements it for the type of the impl block.
+    /// Outside of the impl block, the methods are then called via the trait.
+    fn to_trait<'a>(
+        &self,
+        ident: &Ident,
+        methods: impl IntoIterator<Item = &'a ImplItemMethod>,
+    ) -> TokenStream {
+        let self_ty = &self.self_ty;
+        let methods: Vec<_> = methods.into_iter().collect();
+        // Patterns aren't allowed in arguments of trait methods without body.
+        let sigs = methods.iter().map(|method| {
+            let cfgs = method.attrs.iter().filter(|attr| attr.path.is_ident("cfg"));
+            let mut sig = method.sig.clone();
+            for arg in sig.inputs.iter_mut() {
+                if let FnArg::Typed(arg) = arg {
+                    *arg.pat = syn::parse_quote!(_);
+                }
+            }
+            quote!(#(#cfgs)* #sig;)
+        });
+        quote! {
+            #[allow(dead_code)]
+            trait #ident {
+                #(#sigs)*
+            }
+
+            impl #ident for #self_ty {
+                #(#methods)*
             }
-            "subnet" => {
-                let lit = attr.value.lit;
-                match lit {
-                    Lit::Str(s) => params.subnet.replace(s.value()),
-                    x => panic!("invalid attribute value '{:#?}': expected string or int", x),
+        }
+    }
+}
+
+/// Replaces `Self` in `tokens` with `self_ty`, for use outside of the impl block.
+fn replace_self(tokens: TokenStream, self_ty: &Type) -> TokenStream {
+    tokens
+        .into_iter()
+        .map(|tt| match tt {
+            TokenTree::Ident(ident) if ident == "Self" => self_ty.to_token_stream(),
+            TokenTree::Group(group) => {
+                let mut replaced =
+                    Group::new(group.delimiter(), replace_self(group.stream(), self_ty));
+                replaced.set_span(group.span());
+                TokenTree::Group(replaced).into()
+            }
+            tt => tt.into(),
+        })
+        .collect()
+}
+
+pub(super) fn remote_impl(attrs: GwasmAttrs, mut item: ItemImpl) -> syn::Result<TokenStream> {
+    if let Some(attr) = attrs.attrs.first() {
+        return Err(syn::Error::new_spanned(
+            &attr.ident,
+            "attributes of impl blocks are unsupported: put them on the methods instead",
+        ));
+    }
+    if let Some(attr) = attrs.instantiate {
+        return Err(syn::Error::new_spanned(
+            &attr.ident,
+            "attributes of impl blocks are unsupported: put them on the methods instead",
+        ));
+    }
+    if let Some((_, path, _)) = &item.trait_ {
+        return Err(syn::Error::new_spanned(
+            path,
+            "'remote_fn' is unsupported on trait impls",
+        ));
+    }
+    if !item.generics.params.is_empty() {
+        return Err(syn::Error::new_spanned(
+            &item.generics,
+            "'remote_fn' is unsupported on generic impl blocks",
+        ));
+    }
+
+    // Split off `remote_fn` attributes of the methods.
+    let mut remote = vec![];
+    let mut methods = vec![];
+    for (i, impl_item) in item.items.iter_mut().enumerate() {
+        if let ImplItem::Method(method) = impl_item {
+            let pos = method.attrs.iter().position(|attr| {
+                matches!(attr.path.segments.last(), Some(segment) if segment.ident == "remote_fn")
+            });
+            if let Some(pos) = pos {
+                let attr = method.attrs.remove(pos);
+                let attrs = if attr.tokens.is_empty() {
+                    GwasmAttrs {
+                        attrs: vec![],
+                        instantiate: None,
+                    }
+                } else {
+                    attr.parse_args()?
                 };
+                remote.push((i, attrs));
+            }
+            // `#[cfg]` attributes of the annotated methods are evaluated before their modules
+            // are written out.
+            let mut method = method.clone();
+            method.vis = Visibility::Inherited;
+            if pos.is_some() {
+                method.attrs.retain(|attr| !attr.path.is_ident("cfg"));
+            }
+            methods.push(method);
+        }
+    }
+    let imp = ImplContext {
+        self_ty: item.self_ty.clone(),
+        methods,
+    };
+
+    for (i, attrs) in remote.into_iter().rev() {
+        let method = match item.items.remove(i) {
+            ImplItem::Method(method) => method,
+            _ => unreachable!(),
+        };
+        let f = ItemFn {
+            attrs: method.attrs,
+            vis: method.vis,
+            sig: method.sig,
+            block: Box::new(method.block),
+        };
+        let expanded = expand(attrs, f, Some(&imp))?;
+        item.items.insert(i, ImplItem::Verbatim(expanded));
+    }
+    Ok(item.into_token_stream())
+}
+
+fn expand(attrs: GwasmAttrs, f: ItemFn, imp: Option<&ImplContext>) -> syn::Result<TokenStream> {
+    // Parse attributes
+    let params = GwasmParams::from_attrs(attrs.attrs)?;
+
+    // The function is called from plain `main` of the Wasm module.
+    if let Some(asyncness) = &f.sig.asyncness {
+        return Err(syn::Error::new_spanned(
+            asyncness,
+            "async functions are unsupported",
+        ));
+    }
+    if let Some(unsafety) = &f.sig.unsafety {
+        return Err(syn::Error::new_spanned(
+            unsafety,
+            "unsafe functions are unsupported",
+        ));
+    }
+
+    // `#[cfg]` attributes apply to all of the generated items, including the Wasm module,
+    // while the others, such as doc comments, apply to the expanded function only.
+    let (cfgs, fn_attrs): (Vec<_>, Vec<_>) = f
+        .attrs
+        .iter()
+        .cloned()
+        .partition(|attr| attr.path.is_ident("cfg"));
+    let mut preserved = f.clone();
+    preserved.attrs = fn_attrs.clone();
+    let preserved = preserved.into_token_stream();
+
+    // Resolve the instantiations of generic functions
+    let mut type_params = vec![];
+    for param in &f.sig.generics.params {
+        match param {
+            GenericParam::Type(param) => type_params.push(param.ident.clone()),
+            GenericParam::Lifetime(param) => {
+                return Err(syn::Error::new_spanned(
+                    param,
+                    "lifetime parameters are unsupported",
+                ))
+            }
+            GenericParam::Const(param) => {
+                return Err(syn::Error::new_spanned(
+                    param,
+                    "const parameters are unsupported",
+                ))
             }
-            x => panic!(
-                "unexpected attribute '{}': expected 'budget', 'timeout', or 'subnet'",
-                x
-            ),
         }
     }
+    // Modules are prefixed with the name of the crate, since crates of a workspace share
+    // gfaas_modules crate, and the path of the module within it, and modules compiled from
+    // methods with the type of the impl block.
+    let mut name = crate_name();
+    name.push_str(&module_path());
+    if let Some(imp) = imp {
+        push_words(&mut name, &imp.self_ty);
+    }
+    push_words(&mut name, &f.sig.ident);
+    let name = name.trim_start_matches('_').to_owned();
+    let mut modules = resolve_modules(&name, &type_params, &f.sig.generics, attrs.instantiate)?;
 
     // Validate and extract arguments
-    let args = validate_extract_args(f.args.iter().map(|x| x.clone()));
+    let args = validate_extract_args(f.sig.inputs.iter().cloned(), imp.is_some())?
+        .into_iter()
+        .enumerate()
+        .map(|(i, (pat, ty))| Arg::new(i, &pat, ty))
+        .collect::<syn::Result<Vec<_>>>()?;
+    let return_type = extract_return_type(&f.sig.output);
     // Expand into gWasm connector code
     let fn_vis = f.vis;
-    let fn_ident = f.ident;
-    let fn_args = f.args;
-    let return_type = validate_extract_return_type(&f.ret);
+    let fn_ident = f.sig.ident;
 
+    // The host picks the module to run based on `TypeId` of the type parameters, and so
+    // requires them to be `'static`.
+    let mut generics = f.sig.generics;
+    for param in generics.type_params_mut() {
+        param.bounds.push(syn::parse_quote!('static));
+    }
+    let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+    let turbofish = ty_generics.as_turbofish();
     let run_local = params.run_local.unwrap_or(false);
     let budget = params.budget.unwrap_or(100);
     let timeout = params.timeout.unwrap_or(10 * 60);
     let subnet = params.subnet.unwrap_or("devnet-alpha.2".to_string());
+    let format = params.format.unwrap_or(SerdeFormat::Json);
+    let host_format = format.variant();
+    let retries = params.retries.unwrap_or(0);
+    let backoff = params.backoff.unwrap_or(1);
+    let min_mem_gib = params.min_mem_gib.iter();
+    let min_storage_gib = params.min_storage_gib.iter();
+    let min_cpu_threads = params.min_cpu_threads.iter();
+    let datadir = params.datadir.iter();
+    let app_key = params.app_key.iter();
+    let api_url = params.api_url.iter();
+    let constraints = params
+        .constraints
+        .iter()
+        .flatten()
+        .map(RawConstraint::to_tokens);
 
-    let mut local_input_args = vec![];
-    let mut remote_input_args = vec![];
-    let input_file_names: Vec<_> = (0..args.len()).map(|i| format!("in{}", i)).collect();
-    for (name, (arg, _)) in input_file_names.iter().zip(args.iter()) {
-        let ts = quote! {
-            let input_path = vol.join(#name);
-            let serialized = serde_json::to_vec(&#arg).context("serializing input data")?;
-            fs::write(&input_path, serialized).context("writing serialized data to file")?;
-        };
-        local_input_args.push(ts);
+    let arg_idents: Vec<_> = args.iter().map(|arg| &arg.ident).collect();
+    let arg_tys: Vec<_> = args.iter().map(|arg| &arg.ty).collect();
+    let owned_tys: Vec<_> = args.iter().map(|arg| &arg.owned).collect();
+    let in_idents: Vec<_> = (0..args.len()).map(|i| format_ident!("in{}", i)).collect();
+    let in_args: Vec<_> = args
+        .iter()
+        .zip(&in_idents)
+        .map(|(arg, ident)| arg.pass(ident))
+        .collect();
+    let item_args: Vec<_> = args
+        .iter()
+        .zip(&arg_idents)
+        .map(|(arg, ident)| arg.pass(ident))
+        .collect();
+
+    // Methods are called via a trait implemented for the type of the impl block, since they
+    // can't be redefined on the type itself.
+    let (native_prelude, callee) = match imp {
+        Some(imp) => {
+            let self_ty = &imp.self_ty;
+            let method = imp.methods.iter().filter(|m| m.sig.ident == fn_ident);
+            (
+                imp.to_trait(&format_ident!("Native"), method),
+                quote!(<#self_ty as Native>::#fn_ident),
+            )
+        }
+        None => (preserved.clone(), quote!(#fn_ident)),
+    };
+
+    // With `testing` feature enabled, the body is also compiled natively so that it can
+    // be invoked in-process by `gfaas::testing::MockBackend`.
+    let native = if cfg!(feature = "testing") {
+        quote! {
+            let invocation = invocation.with_native(|inputs: Vec<Vec<u8>>| -> gfaas::__private::anyhow::Result<Vec<u8>> {
+                #native_prelude
 
+                use gfaas::__private::anyhow::Context;
+
+                #[allow(unused_mut, unused_variables)]
+                let mut inputs = inputs.into_iter();
+                #(
+                    let #in_idents = inputs.next().context("missing input data")?;
+                    let #in_idents: #owned_tys = #host_format.deserialize(&#in_idents).context("deserializing input data")?;
+                )*
+                let res = #callee #turbofish(#(#in_args),*);
+                let serialized = #host_format.serialize(&res).context("serializing output data")?;
+                Ok(serialized)
+            });
+        }
+    } else {
+        quote!()
+    };
+
+    // Outside of the impl block, methods are called via a trait implemented for the type,
+    // which is shared with the Wasm modules.
+    let (wasm_prelude, callee) = match imp {
+        Some(imp) => {
+            let self_ty = &imp.self_ty;
+            let remote = imp.to_trait(&format_ident!("Remote"), &imp.methods);
+            (remote, quote!(<#self_ty as Remote>::#fn_ident))
+        }
+        None => (preserved, callee),
+    };
+    let mut inputs = vec![];
+    for (in_ident, owned) in in_idents.iter().zip(&owned_tys) {
+        let deserialize = format.deserialize(in_ident);
+        let owned = match imp {
+            Some(imp) => replace_self(owned.into_token_stream(), &imp.self_ty),
+            None => owned.into_token_stream(),
+        };
         let ts = quote! {
-            let input_path = workspace.path().join(#name);
-            let serialized = serde_json::to_vec(&#arg).context("serializing input data")?;
-            fs::write(&input_path, serialized).context("writing serialized data to file")?;
+            let #in_ident = inputs.next().unwrap();
+            let #in_ident: #owned = #deserialize.unwrap();
         };
-        remote_input_args.push(ts);
+        inputs.push(ts);
     }
+    let serialize = format.serialize(&format_ident!("res"));
 
-    let mut local_input_paths = vec![];
-    let mut remote_input_paths = vec![];
-    let mut remote_input_names = vec![];
-    for name in input_file_names {
-        let ts = quote! {
-            ["/workdir/", #name].join(""),
-        };
-        local_input_paths.push(ts);
+    // push body of the function into a Wasm module
+    let mut contents = vec![];
+    for module in &mut modules {
+        // Type parameters of generic functions are aliased to the types of the instantiation.
+        let types = &module.types;
+        let module_contents = quote! {
+            // Items shared with the Wasm modules live in the library of gfaas_modules crate.
+            #[allow(unused_imports)]
+            use gfaas_modules::*;
 
-        let ts = quote! {
-            format!("/workdir/{}", #name)
-        };
-        remote_input_names.push(ts);
+            #wasm_prelude
 
-        let ts = quote! {
-            upload(workspace.path().join(#name), format!("/workdir/{}", #name));
+            fn main() {
+                use std::convert::TryInto;
+                use std::fs;
+                use std::env;
+
+                #(type #type_params = #types;)*
+
+                let mut args: Vec<_> = env::args().collect();
+                let out = args.pop().unwrap();
+                let input = fs::read(args.pop().unwrap()).unwrap();
+
+                // Inputs are stored in a single file, each prefixed with its length
+                // encoded as little-endian u64.
+                let mut split = vec![];
+                let mut rest = &input[..];
+                while !rest.is_empty() {
+                    let (len, tail) = rest.split_at(8);
+                    let len = u64::from_le_bytes(len.try_into().unwrap()) as usize;
+                    let (data, tail) = tail.split_at(len);
+                    split.push(data);
+                    rest = tail;
+                }
+                #[allow(unused_mut, unused_variables)]
+                let mut inputs = split.into_iter();
+                #(#inputs)*
+
+                let res = #callee #turbofish(#(#in_args),*);
+                let serialized = #serialize.unwrap();
+
+                fs::write(out, &serialized).unwrap();
+
+                // When run as a task of a batch on Golem, reports which one along with the size of
+                // its output, for the backend to tell which activity computed which output.
+                if let [_, task] = &args[..] {
+                    println!("gfaas-task {} {}", task, serialized.len());
+                }
+            }
         };
-        remote_input_paths.push(ts);
-    }
-
-    let output = {
-        if run_local {
-            quote! {
-                #fn_vis async fn #fn_ident(#fn_args) -> std::result::Result<#return_type, gfaas::Error> {
-                    use gfaas::__private::anyhow::{anyhow, Context};
-                    use gfaas::__private::tokio::task;
-                    use gfaas::__private::tempfile::tempdir;
-                    use gfaas::__private::ya_runtime_wasi;
-                    use gfaas::__private::package::Package;
-                    use gfaas::__private::serde_json;
-                    use std::{fs, env, path::PathBuf};
-
-                    task::spawn_blocking(move || {
-                        // 0. Create temp workspace
-                        let workspace = tempdir().context("creating temp dir")?;
-
-                        // 1. Prepare zip archive
-                        let exe_path = env::current_exe().context("extracting path to the current exe")?;
-                        let parent = exe_path
-                            .parent()
-                            .ok_or_else(|| anyhow!("path to the current exe without parent: '{}'", exe_path.display()))?;
-                        let module_name = format!("{}", stringify!(#fn_ident));
-                        let wasm = parent.join(format!("{}.wasm", module_name));
-                        let package_path = workspace.path().join("pkg.zip");
-                        let mut package = Package::new();
-                        package.add_module_from_path(wasm).context("adding Wasm module from path")?;
-                        package.write(&package_path).context("saving Yagna zip package to file")?;
-
-                        // 2. Deploy
-                        ya_runtime_wasi::deploy(workspace.path(), &package_path).context("deploying Yagna package")?;
-                        ya_runtime_wasi::start(workspace.path()).context("executing Yagna start command")?;
-
-                        let deployment = ya_runtime_wasi::DeployFile::load(workspace.path()).context("loading deployed Yagna package")?;
-                        let vol = deployment
-                            .vols()
-                            .find(|vol| vol.path.starts_with("/workdir"))
-                            .map(|vol| workspace.path().join(&vol.name))
-                            .context("extracting workdir path from Yagna package")?;
-
-                        let output_file_name = "out".to_owned();
-                        let output_path = vol.join(&output_file_name);
-
-                        #(#local_input_args)*
-
-                        // 3. Run
-                        ya_runtime_wasi::run(
-                            workspace.path(),
-                            &module_name,
-                            vec![
-                                #(#local_input_paths)*
-                                ["/workdir/", &output_file_name].join(""),
-                            ],
-                        ).context("executing Yagna run command")?;
-
-                        // 4. Collect the results
-                        let output_data = fs::read(output_path).context("reading output data from file")?;
-                        let res = serde_json::from_slice(&output_data).context("deserializing output data")?;
-                        Ok(res)
-                    }).await?
+
+        // Modules are named after the function and a hash of their contents too, so that
+        // functions of the same name in inline modules don't overwrite each other.
+        let hash = stable_hash(&[&module.name, &module_contents.to_string()]);
+        module.name.push('_');
+        module.name.push_str(&hash);
+        contents.push(module_contents);
+    }
+
+    // When built with `gfaas build --embed`, the modules are already compiled by the time the
+    // crate is, and so they are embedded into the binary instead of being looked up at runtime.
+    let embed_dir = env::var("GFAAS_EMBED_DIR").ok();
+    let new_invocations: Vec<_> = modules
+        .iter()
+        .map(|module| {
+            let name = &module.name;
+            match &embed_dir {
+                Some(embed_dir) => {
+                    let path = Path::new(embed_dir).join(format!("{}.wasm", name));
+                    let path = path.to_string_lossy();
+                    quote!(gfaas::Invocation::new(#name).with_module(include_bytes!(#path)))
                 }
+                None => quote!(gfaas::Invocation::new(#name)),
             }
-        } else {
-            quote! {
-                #fn_vis async fn #fn_ident(#fn_args) -> std::result::Result<#return_type, gfaas::Error> {
-                    use gfaas::__private::anyhow::{self, Context, anyhow};
-                    use gfaas::__private::futures::future::{select, FutureExt};
-                    use gfaas::__private::tempfile::tempdir;
-                    use gfaas::__private::package::Package;
-                    use gfaas::__private::yarapi::{commands, requestor::{self, CommandList, Image::Wasm, Requestor}};
-                    use gfaas::__private::ya_agreement_utils::{constraints, ConstraintKey, Constraints};
-                    use gfaas::__private::serde_json;
-                    use std::{fs, env, path::{Path, PathBuf}, collections::HashMap};
-
-                    // 1. Create temp workspace
-                    let workspace = tempdir().context("creating temp dir")?;
-
-                    // 2. Prepare package
-                    let exe_path = env::current_exe().context("extracting path to the current exe")?;
-                    let parent = exe_path
-                        .parent()
-                        .ok_or_else(|| anyhow!("path to the current exe without parent: '{}'", exe_path.display()))?;
-                    let module_name = format!("{}", stringify!(#fn_ident));
-                    let wasm = parent.join(format!("{}.wasm", module_name));
-                    let package_path = workspace.path().join("pkg.zip");
-                    let mut package = Package::new();
-                    package.add_module_from_path(wasm).context("adding Wasm module from path")?;
-                    package.write(&package_path).context("saving Yagna zig package to file")?;
-
-                    // 3. Prepare workspace
-                    let output_path = workspace.path().join("out");
-
-                    #(#remote_input_args)*
-
-                    // 4. Run
-                    Requestor::new(
-                        "custom",
-                        Wasm((0, 0, 0).into()),
-                        requestor::Package::Archive(package_path)
-                    )
-                    .with_subnet(#subnet)
-                    .with_max_budget_ngnt(#budget)
-                    .with_timeout(std::time::Duration::from_secs(#timeout))
-                    .with_constraints(constraints![
-                        "golem.inf.mem.gib" > 0.5,
-                        "golem.inf.storage.gib" > 1.0,
-                    ])
-                    .with_tasks(vec![commands! {
-                        #(#remote_input_paths)*
-                        run(module_name, #(#remote_input_names),*, "/workdir/out");
-                        download("/workdir/out", &output_path);
-                    }].into_iter())
-                    .on_completed(|activity_id, output| {
-                        println!("{} => {:#?}", activity_id, output);
-                    })
-                    .run()
-                    .await?;
-                    // .context("running task on Yagna")?;
+        })
+        .collect();
+    let new_invocation = if type_params.is_empty() {
+        new_invocations[0].clone()
+    } else {
+        let types = modules.iter().map(|module| {
+            let types = &module.types;
+            quote!((#(#types,)*))
+        });
+        quote! {{
+            let params = std::any::TypeId::of::<(#(#type_params,)*)>();
+            #(
+                if params == std::any::TypeId::of::<#types>() {
+                    #new_invocations
+                } else
+            )*
+            {
+                return Err(gfaas::Error::NotInstantiated {
+                    function: stringify!(#fn_ident),
+                    types: std::any::type_name::<(#(#type_params,)*)>(),
+                });
+            }
+        }}
+    };
 
-                    let output_data = fs::read(&output_path).context("reading output data from file")?;
-                    let res = serde_json::from_slice(&output_data).context("deserializing output data")?;
+    // Functions returning `Result<T, E>` get the error `E` propagated back to the caller
+    // as `gfaas::RemoteError::Application`. So do the batched ones, where each output is
+    // `Result<T, gfaas::RemoteError<E>>`, and failing to deserialize it fails only that output.
+    let (output_type, unpack_output, batch_output_type, unpack_batch_output) =
+        match extract_result_types(&return_type) {
+            Some((ok_type, err_type)) => (
+                quote!(std::result::Result<#ok_type, gfaas::RemoteError<#err_type>>),
+                quote! {
+                    let res: std::result::Result<#ok_type, #err_type> =
+                        gfaas::__private::deserialize(#host_format, &output_data)?;
+                    res.map_err(gfaas::RemoteError::Application)
+                },
+                quote!(std::result::Result<#ok_type, gfaas::RemoteError<#err_type>>),
+                quote! {
+                    Ok(gfaas::__private::deserialize::<std::result::Result<#ok_type, #err_type>>(
+                        #host_format,
+                        output_data,
+                    )
+                    .map_err(gfaas::RemoteError::Infrastructure)
+                    .and_then(|res| res.map_err(gfaas::RemoteError::Application)))
+                },
+            ),
+            None => (
+                quote!(std::result::Result<#return_type, gfaas::Error>),
+                quote! {
+                    let res = gfaas::__private::deserialize(#host_format, &output_data)?;
                     Ok(res)
+                },
+                quote!(#return_type),
+                quote!(gfaas::__private::deserialize(#host_format, output_data)),
+            ),
+        };
+
+    // Items of the companion module are visible wherever the function itself is.
+    let batch_vis = match &fn_vis {
+        Visibility::Inherited => quote!(pub(super)),
+        Visibility::Restricted(r) if r.in_token.is_none() && r.path.is_ident("self") => {
+            quote!(pub(super))
+        }
+        Visibility::Restricted(r) if r.in_token.is_none() && r.path.is_ident("super") => {
+            quote!(pub(in super::super))
+        }
+        vis => quote!(#vis),
+    };
+    // Batched inputs are owned, and are borrowed as needed when creating the invocations.
+    let item_type = match owned_tys.len() {
+        1 => {
+            let ty = owned_tys[0];
+            quote!(#ty)
+        }
+        _ => quote!((#(#owned_tys),*)),
+    };
+    let item_pat = match arg_idents.len() {
+        1 => {
+            let ident = arg_idents[0];
+            quote!(#ident)
+        }
+        _ => quote!((#(#arg_idents),*)),
+    };
+    let mut batch_generics = generics.clone();
+    batch_generics.params.push(syn::parse_quote!(__GfaasInputs));
+    batch_generics
+        .make_where_clause()
+        .predicates
+        .push(syn::parse_quote!(__GfaasInputs: IntoIterator<Item = #item_type>));
+    let (batch_impl_generics, _, batch_where_clause) = batch_generics.split_for_impl();
+    let batch_doc = format!(
+        "Batch API of [`{0}`](fn.{0}.html), which runs many invocations of the function \
+         in a single Golem agreement.",
+        fn_ident
+    );
+
+    // Methods can't have a companion module, and so only the function creating the invocation
+    // is generated for them, as a hidden associated function.
+    let (invocation_vis, invocation_ident, invocation_path) = match imp {
+        Some(_) => {
+            let ident = format_ident!("__{}_invocation", fn_ident);
+            (quote!(), ident.clone(), quote!(Self::#ident))
+        }
+        None => (
+            quote!(pub(super)),
+            format_ident!("invocation"),
+            quote!(#fn_ident::invocation),
+        ),
+    };
+    let invocation = quote! {
+        #[doc(hidden)]
+        #invocation_vis fn #invocation_ident #impl_generics(#(#arg_idents: #arg_tys),*) -> std::result::Result<gfaas::Invocation, gfaas::Error> #where_clause {
+            // Have cargo rebuild the crate whenever embedding of the modules is switched on
+            // or off, since the variable is otherwise only read by this macro.
+            let _ = option_env!("GFAAS_EMBED_DIR");
+            let invocation = #new_invocation
+                .with_budget(#budget)
+                .with_timeout(std::time::Duration::from_secs(#timeout))
+                .with_subnet(#subnet)
+                .with_retries(#retries)
+                .with_backoff(std::time::Duration::from_secs(#backoff))
+                #(.with_min_mem_gib(#min_mem_gib))*
+                #(.with_min_storage_gib(#min_storage_gib))*
+                #(.with_min_cpu_threads(#min_cpu_threads))*
+                #(#constraints)*
+                #(.with_datadir(#datadir))*
+                #(.with_app_key(#app_key))*
+                #(.with_api_url(#api_url))*
+                #(.with_input(gfaas::__private::serialize(#host_format, &#arg_idents)?))*;
+            #native
+            Ok(invocation)
+        }
+    };
+    let remote_fn = quote! {
+        #(#cfgs)*
+        #(#fn_attrs)*
+        #fn_vis async fn #fn_ident #impl_generics(#(#arg_idents: #arg_tys),*) -> #output_type #where_clause {
+            let invocation = #invocation_path #turbofish(#(#arg_idents),*)?;
+            let output_data = gfaas::__private::run(#run_local, invocation).await?;
+            #unpack_output
+        }
+    };
+
+    let mut output = if imp.is_some() {
+        quote! {
+            #remote_fn
+
+            #(#cfgs)*
+            #invocation
+        }
+    } else {
+        // The companion module can't refer to the items declared in a fn body, and so neither
+        // can the function be declared in one. Should it be, referring to the marker from the
+        // module fails, pointing at the function.
+        let marker = format_ident!("__{}_must_be_declared_outside_of_fn_bodies", fn_ident);
+        let marker_ref = quote_spanned!(fn_ident.span()=> super::#marker);
+        quote! {
+            #remote_fn
+
+            #(#cfgs)*
+            #[doc(hidden)]
+            #[allow(non_upper_case_globals)]
+            const #marker: () = ();
+
+            #(#cfgs)*
+            #[doc = #batch_doc]
+            #fn_vis mod #fn_ident {
+                use super::*;
+
+                const _: () = #marker_ref;
+
+                #invocation
+
+                /// Runs the function on each of `inputs` as a single batch, returning the outputs
+                /// in the order of `inputs`. Functions taking multiple arguments expect them in
+                /// a tuple.
+                #batch_vis async fn batch #batch_impl_generics(inputs: __GfaasInputs) -> std::result::Result<Vec<#batch_output_type>, gfaas::Error>
+                #batch_where_clause
+                {
+                    let mut invocations = vec![];
+                    for #item_pat in inputs {
+                        invocations.push(invocation #turbofish(#(#item_args),*)?);
+                    }
+                    let outputs = gfaas::__private::run_batch(#run_local, invocations).await?;
+                    outputs
+                        .iter()
+                        .map(|output_data| #unpack_batch_output)
+                        .collect()
+                }
+
+                /// Runs the function on each of `inputs` as a single batch, like [`batch`](fn.batch.html),
+                /// but yields the outputs as soon as they arrive, along with the index of the input and
+                /// the id of the activity which computed the output.
+                #batch_vis fn stream #batch_impl_generics(
+                    inputs: __GfaasInputs,
+                ) -> impl gfaas::__private::futures::Stream<
+                    Item = std::result::Result<gfaas::Completed<#batch_output_type>, gfaas::Error>,
+                >
+                #batch_where_clause
+                {
+                    use gfaas::__private::futures::StreamExt;
+
+                    let invocations = inputs
+                        .into_iter()
+                        .map(|#item_pat| invocation #turbofish(#(#item_args),*))
+                        .collect();
+                    gfaas::__private::run_streaming(#run_local, invocations).map(|completed| {
+                        let completed = completed?;
+                        let output_data = &completed.output;
+                        let output = #unpack_batch_output?;
+                        Ok(gfaas::Completed {
+                            index: completed.index,
+                            activity_id: completed.activity_id,
+                            output,
+                        })
+                    })
                 }
             }
         }
     };
 
-    let mut inputs = vec![];
-    let mut input_args = VecDeque::with_capacity(args.len());
-    for i in 0..args.len() {
-        let in_ident = format_ident!("in{}", i);
-        let ts = quote! {
-            let next_arg = args.pop().unwrap();
-            let #in_ident = fs::read(next_arg).unwrap();
-            let #in_ident = serde_json::from_slice(&#in_ident).unwrap();
-        };
-        inputs.push(ts);
-        input_args.push_front(quote!(#in_ident));
+    // Whether the function is compiled at all is only known once its `#[cfg]` attributes are
+    // evaluated, and so the modules are written out by another macro subject to them.
+    let format_name = format.name();
+    for (module, contents) in modules.iter().zip(&contents) {
+        let name = &module.name;
+        output.extend(quote! {
+            #(#cfgs)*
+            gfaas::__private::wasm_module! { #name, #format_name, #contents }
+        });
     }
-    let args_in_order = input_args.as_slices().0;
-    let contents = quote! {
-        #preserved
 
-        fn main() {
-            use std::fs;
-            use std::env;
+    Ok(output)
+}
 
-            let mut args: Vec<_> = env::args().collect();
-            let out = args.pop().unwrap();
-            #(#inputs)*
+/// Wasm module compiled from an annotated function, as passed to `wasm_module!` by the
+/// expanded function.
+pub struct WasmModule {
+    name: LitStr,
+    format: LitStr,
+    contents: TokenStream,
+}
 
-            let res = #fn_ident(#(#args_in_order),*);
-            let serialized = serde_json::to_vec(&res).unwrap();
+impl Parse for WasmModule {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let name = input.parse()?;
+        input.parse::<Token![,]>()?;
+        let format = input.parse()?;
+        input.parse::<Token![,]>()?;
+        Ok(WasmModule {
+            name,
+            format,
+            contents: input.parse()?,
+        })
+    }
+}
 
-            fs::write(out, &serialized).unwrap();
-        }
+pub(super) fn wasm_module_impl(module: WasmModule) -> syn::Result<TokenStream> {
+    // If the project wasn't built with gfaas tool (e.g., with plain `cargo test`), there is
+    // nowhere to put the module, and any attempt at running the function on a backend other
+    // than `gfaas::testing::MockBackend` will fail at runtime.
+    let out_dir = match env::var("GFAAS_OUT_DIR") {
+        Ok(out_dir) => out_dir,
+        Err(_) => return Ok(TokenStream::new()),
     };
-
-    // push body of the function into a Wasm module
-    let out_dir = env::var("GFAAS_OUT_DIR")
-        .expect("GFAAS_OUT_DIR should be defined. Did you build the project with gfaas tool?");
-    let out_path = Path::new(&out_dir)
-        .join("gfaas_modules")
+    let module_path = Path::new(&out_dir).join("gfaas_modules");
+    let name = module.name.value();
+    let out_path = module_path
         .join("src")
         .join("bin")
-        .join(format!("{}.rs", fn_ident.to_string()));
-    let mut out = File::create(out_path).unwrap_or_else(|_| {
-        panic!(
-            "generating Wasm src file {}",
-            [&out_dir, "gfaas.rs"].join("/")
+        .join(format!("{}.rs", name));
+    // Names of the modules are hashes of their contents, so a module of the same name with
+    // different contents can only come from a hash collision, which would otherwise go unnoticed.
+    let contents = format!("{}\n", module.contents);
+    if let Ok(existing) = fs::read_to_string(&out_path) {
+        if existing != contents {
+            return Err(syn::Error::new_spanned(
+                &module.name,
+                format!(
+                    "Wasm module '{}' collides with a module of another function: rename either of them",
+                    name
+                ),
+            ));
+        }
+    }
+    fs::write(&out_path, contents).map_err(|err| {
+        syn::Error::new(
+            Span::call_site(),
+            format!("writing Wasm src file '{}': {}", out_path.display(), err),
         )
+    })?;
+
+    // Record module metadata for gfaas tool, which uses it to assemble the dependencies
+    // of gfaas_modules crate.
+    let meta_dir = module_path.join("meta");
+    let meta_path = meta_dir.join(format!("{}.json", name));
+    let meta = serde_json::json!({
+        "crate": crate_name(),
+        "build": build_id(),
+        "format": module.format.value(),
     });
-    writeln!(out, "{}", contents).unwrap();
+    fs::create_dir_all(&meta_dir)
+        .and_then(|_| fs::write(&meta_path, meta.to_string()))
+        .map_err(|err| {
+            syn::Error::new(
+                Span::call_site(),
+                format!(
+                    "writing Wasm module metadata '{}': {}",
+                    meta_path.display(),
+                    err
+                ),
+            )
+        })?;
+
+    Ok(TokenStream::new())
+}
+
+/// Item shared with the Wasm modules, as passed to `shared_item!` by the expanded item.
+pub struct SharedItem {
+    name: LitStr,
+    contents: TokenStream,
+}
+
+impl Parse for SharedItem {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let name = input.parse()?;
+        input.parse::<Token![,]>()?;
+        Ok(SharedItem {
+            name,
+            contents: input.parse()?,
+        })
+    }
+}
+
+pub(super) fn shared_item_impl(item: SharedItem) -> syn::Result<TokenStream> {
+    // gfaas tool includes the shared items in lib.rs of gfaas_modules crate.
+    let out_dir = match env::var("GFAAS_OUT_DIR") {
+        Ok(out_dir) => out_dir,
+        Err(_) => return Ok(TokenStream::new()),
+    };
+    // Items are grouped by crate, so that gfaas tool can tell which of them are left over from
+    // the previous builds of the crate.
+    let shared_dir = Path::new(&out_dir)
+        .join("gfaas_modules")
+        .join("src")
+        .join("shared")
+        .join(crate_name());
+    let shared_path = shared_dir.join(format!("{}.rs", item.name.value()));
+    // The id of the build leads the file, so that gfaas tool can tell stale items.
+    let contents = format!("// gfaas-build {}\n{}\n", build_id(), item.contents);
+    // Shared items end up in a single namespace, and so items of the same kind and name in
+    // different modules of the crate would overwrite each other. An item written by another
+    // build is merely out of date though.
+    if let Ok(existing) = fs::read_to_string(&shared_path) {
+        let same_build =
+            !build_id().is_empty() && existing.lines().next() == contents.lines().next();
+        if same_build && existing != contents {
+            return Err(syn::Error::new_spanned(
+                &item.name,
+                format!(
+                    "shared item '{}' collides with another shared item of the same name: rename either of them",
+                    item.name.value()
+                ),
+            ));
+        }
+    }
+    fs::create_dir_all(&shared_dir)
+        .and_then(|_| fs::write(&shared_path, contents))
+        .map_err(|err| {
+            syn::Error::new(
+                Span::call_site(),
+                format!(
+                    "writing shared Wasm src file '{}': {}",
+                    shared_path.display(),
+                    err
+                ),
+            )
+        })?;
+
+    Ok(TokenStream::new())
+}
+
+/// Makes the item usable from outside of gfaas_modules crate, into which it is copied. Struct
+/// fields and items of inherent impls are always made public, while items nested in modules
+/// keep private visibility, since they couldn't have been used from elsewhere anyway.
+fn publicize(item: &mut Item, nested: bool) {
+    fn publicize_vis(vis: &mut Visibility, nested: bool) {
+        if !nested || !matches!(vis, Visibility::Inherited) {
+            *vis = syn::parse_quote!(pub);
+        }
+    }
+
+    let pub_vis: Visibility = syn::parse_quote!(pub);
+    match item {
+        Item::Const(item) => publicize_vis(&mut item.vis, nested),
+        Item::Enum(item) => publicize_vis(&mut item.vis, nested),
+        Item::Fn(item) => publicize_vis(&mut item.vis, nested),
+        Item::Static(item) => publicize_vis(&mut item.vis, nested),
+        Item::Trait(item) => publicize_vis(&mut item.vis, nested),
+        Item::Type(item) => publicize_vis(&mut item.vis, nested),
+        Item::Use(item) => publicize_vis(&mut item.vis, nested),
+        Item::Struct(item) => {
+            publicize_vis(&mut item.vis, nested);
+            for field in item.fields.iter_mut() {
+                field.vis = pub_vis.clone();
+            }
+        }
+        Item::Union(item) => {
+            publicize_vis(&mut item.vis, nested);
+            for field in item.fields.named.iter_mut() {
+                field.vis = pub_vis.clone();
+            }
+        }
+        Item::Impl(item) if item.trait_.is_none() => {
+            for impl_item in item.items.iter_mut() {
+                match impl_item {
+                    ImplItem::Const(impl_item) => impl_item.vis = pub_vis.clone(),
+                    ImplItem::Method(impl_item) => impl_item.vis = pub_vis.clone(),
+                    ImplItem::Type(impl_item) => impl_item.vis = pub_vis.clone(),
+                    _ => {}
+                }
+            }
+        }
+        Item::Mod(item) => {
+            publicize_vis(&mut item.vis, nested);
+            if let Some((_, items)) = &mut item.content {
+                for item in items {
+                    publicize(item, true);
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+fn item_attrs(item: &mut Item) -> &mut Vec<Attribute> {
+    match item {
+        Item::Const(item) => &mut item.attrs,
+        Item::Enum(item) => &mut item.attrs,
+        Item::Fn(item) => &mut item.attrs,
+        Item::Impl(item) => &mut item.attrs,
+        Item::Mod(item) => &mut item.attrs,
+        Item::Static(item) => &mut item.attrs,
+        Item::Struct(item) => &mut item.attrs,
+        Item::Trait(item) => &mut item.attrs,
+        Item::Type(item) => &mut item.attrs,
+        Item::Union(item) => &mut item.attrs,
+        Item::Use(item) => &mut item.attrs,
+        _ => unreachable!("only items which can be shared are expected"),
+    }
+}
+
+pub(super) fn shared_impl(attr: TokenStream, item: Item) -> syn::Result<TokenStream> {
+    if !attr.is_empty() {
+        return Err(syn::Error::new_spanned(
+            attr,
+            "unexpected arguments: 'shared' takes none",
+        ));
+    }
+
+    // Shared items are written out to files named after their kind and name. Impl blocks and
+    // use declarations are nameless, and so their names are derived from their contents.
+    let mut name = match &item {
+        Item::Const(item) => format!("const_{}", item.ident),
+        Item::Enum(item) => format!("enum_{}", item.ident),
+        Item::Fn(item) => format!("fn_{}", item.sig.ident),
+        Item::Static(item) => format!("static_{}", item.ident),
+        Item::Struct(item) => format!("struct_{}", item.ident),
+        Item::Trait(item) => format!("trait_{}", item.ident),
+        Item::Type(item) => format!("type_{}", item.ident),
+        Item::Union(item) => format!("union_{}", item.ident),
+        Item::Mod(item) if item.content.is_some() => format!("mod_{}", item.ident),
+        Item::Mod(item) => {
+            return Err(syn::Error::new_spanned(
+                item,
+                "only inline modules can be shared with Wasm modules",
+            ))
+        }
+        Item::Impl(imp) => {
+            let mut name = "impl".to_owned();
+            push_words(&mut name, &imp.generics.params);
+            if let Some((_, path, _)) = &imp.trait_ {
+                push_words(&mut name, path);
+            }
+            push_words(&mut name, &imp.self_ty);
+            name
+        }
+        Item::Use(item) => {
+            let mut name = "use".to_owned();
+            push_words(&mut name, &item.tree);
+            name
+        }
+        item => {
+            return Err(syn::Error::new_spanned(
+                item,
+                "items of this kind cannot be shared with Wasm modules",
+            ))
+        }
+    };
+    if let Item::Impl(_) | Item::Use(_) = &item {
+        name.push('_');
+        name.push_str(&stable_hash(&[&item.to_token_stream().to_string()]));
+    }
+
+    // The item is copied into a crate of its own, and so it has to be public to be usable
+    // from the Wasm modules. Whether it is compiled at all is only known once its `#[cfg]`
+    // attributes are evaluated, and so it is written out by another macro subject to them.
+    let mut shared = item.clone();
+    publicize(&mut shared, false);
+    let attrs = item_attrs(&mut shared);
+    let (cfgs, rest): (Vec<_>, Vec<_>) =
+        attrs.drain(..).partition(|attr| attr.path.is_ident("cfg"));
+    *attrs = rest;
+
+    // Bodies of remote functions are only compiled into the Wasm modules, and so the item may
+    // well be unused in the crate itself.
+    let mut item = item;
+    item_attrs(&mut item).push(syn::parse_quote!(#[allow(dead_code, unused_imports)]));
+
+    Ok(quote! {
+        #item
 
-    output
+        #(#cfgs)*
+        gfaas::__private::shared_item! { #name, #shared }
+    })
 }
@@ -15,7 +15,13 @@ use std::{
 };
 use structopt::StructOpt;
 
-#[remote_fn(budget = 1000, timeout = 900, subnet = "devnet-alpha.2")]
+#[remote_fn(
+    budget = 1000,
+    timeout = 900,
+    subnet = "devnet-alpha.2",
+    min_mem_gib = 2.0,
+    min_cpu_threads = 2
+)]
 fn generate_proof_on_golem(params: Vec<u8>, preimage: Vec<u8>) -> Vec<u8> {
     use bellman::{
         gadgets::{
@@ -1,24 +1,28 @@
 use anyhow::Result;
-use futures::{
-    lock::Mutex,
-    stream::{self, TryStreamExt},
-};
 use gfaas::remote_fn;
-use std::{fs::File, io::BufWriter, sync::Arc};
+use std::{fs::File, io::BufWriter};
 use structopt::StructOpt;
 
-#[remote_fn(budget = 1000, timeout = 900, subnet = "devnet-alpha.2")]
-fn compute_rectangle(start_y: u32, end_y: u32, width: u32, height: u32) -> Vec<u32> {
+#[gfaas::shared]
+mod fractal {
     use num_complex::Complex;
 
+    pub const MAX_ITER: u32 = 255;
+
     const RE_START: f64 = -2.0;
     const RE_END: f64 = 1.0;
     const IM_START: f64 = -1.0;
     const IM_END: f64 = 1.0;
 
-    fn mandelbrot(c: Complex<f64>) -> u32 {
-        const MAX_ITER: u32 = 255;
+    /// Maps pixel `(x, y)` of the image onto the complex plane.
+    pub fn point(x: u32, y: u32, width: u32, height: u32) -> Complex<f64> {
+        Complex::new(
+            RE_START + (x as f64 / width as f64) * (RE_END - RE_START),
+            IM_START + (y as f64 / height as f64) * (IM_END - IM_START),
+        )
+    }
 
+    pub fn mandelbrot(c: Complex<f64>) -> u32 {
         let mut z = Complex::<f64>::default();
         let mut niter = 0;
 
@@ -27,17 +31,16 @@ fn compute_rectangle(start_y: u32, end_y: u32, width: u32, height: u32) -> Vec<u
             niter += 1;
         }
 
-        return niter;
+        niter
     }
+}
 
+#[remote_fn(budget = 1000, timeout = 900, subnet = "devnet-alpha.2")]
+fn compute_rectangle(start_y: u32, end_y: u32, width: u32, height: u32) -> Vec<u32> {
     let mut output = vec![];
     for y in start_y..end_y {
         for x in 0..width {
-            let c = Complex::new(
-                RE_START + (x as f64 / width as f64) * (RE_END - RE_START),
-                IM_START + (y as f64 / height as f64) * (IM_END - IM_START),
-            );
-            output.push(mandelbrot(c));
+            output.push(fractal::mandelbrot(fractal::point(x, y, width, height)));
         }
     }
     output
@@ -62,19 +65,15 @@ struct Opt {
     in_parallel: u32,
 }
 
-const MAX_CONCURRENT_JOBS: usize = 4;
-
 #[actix_rt::main]
 async fn main() -> Result<()> {
-    const MAX_ITER: u32 = 255;
-
     let opts = Opt::from_args();
 
     let max_row_size = (opts.height as f64 / opts.in_parallel as f64).ceil() as u32;
     let width = opts.width;
     let height = opts.height;
 
-    let mut chunks: Vec<Result<_>> = vec![];
+    let mut chunks = vec![];
     for n in 0..opts.in_parallel {
         let start_y = n * max_row_size;
         let end_y = if start_y + max_row_size > height {
@@ -82,21 +81,12 @@ async fn main() -> Result<()> {
         } else {
             start_y + max_row_size
         };
-        chunks.push(Ok((n, start_y, end_y)));
+        chunks.push((start_y, end_y, width, height));
     }
 
-    let output = Arc::new(Mutex::new(Vec::new()));
-    let chunks = stream::iter(chunks);
-    chunks
-        .try_for_each_concurrent(MAX_CONCURRENT_JOBS, |(n, start_y, end_y)| {
-            let output = Arc::clone(&output);
-            async move {
-                let rect = compute_rectangle(start_y, end_y, width, height).await?;
-                output.lock().await.push((n, rect));
-                Ok(())
-            }
-        })
-        .await?;
+    // All chunks are computed in a single Golem agreement, and the results come back
+    // in the same order as the chunks.
+    let output = compute_rectangle::batch(chunks).await?;
 
     let file = File::create("mandelbrot.png")?;
     let mut w = BufWriter::new(file);
@@ -106,15 +96,10 @@ async fn main() -> Result<()> {
     encoder.set_depth(png::BitDepth::Eight);
     let mut writer = encoder.write_header()?;
 
-    let output = Arc::try_unwrap(output)
-        .expect("container with computation results should be fully filled in by now");
-    let mut output = output.into_inner();
-    output.sort_by(|(x1, _), (x2, _)| x1.cmp(x2));
     let output: Vec<_> = output
         .into_iter()
-        .map(|(_, c)| c)
         .flatten()
-        .map(|c| (MAX_ITER - c) as u8)
+        .map(|c| (fractal::MAX_ITER - c) as u8)
         .collect();
     writer.write_image_data(&output)?;
 
@@ -5,7 +5,7 @@ use futures::{
 use gfaas::remote_fn;
 use std::sync::Arc;
 
-#[remote_fn(budget = 100, timeout = 900, subnet = "devnet-alpha.2")]
+#[remote_fn(budget = 100, timeout = 900, subnet = "devnet-alpha.2", retries = 2)]
 fn partial_sum(r#in: Vec<u64>) -> u64 {
     r#in.into_iter().sum()
 }
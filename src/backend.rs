@@ -0,0 +1,1095 @@
+//! Backends capable of executing `gfaas::remote_fn`-annotated functions.
+//!
+//! The function expanded by `gfaas::remote_fn` serializes its inputs, wraps them in an
+//! [`Invocation`] and hands it over to a [`Backend`] which is then responsible for packaging
+//! the Wasm module, uploading the inputs, running the module and downloading the output.
+//!
+//! Two backends are provided out-of-the-box: [`Golem`] which executes the function on the
+//! Golem Network, and [`Local`] which executes the function locally using `ya-runtime-wasi`.
+//! You can plug in your own backend by implementing the [`Backend`] trait and installing it
+//! with [`set_backend`] for all threads, or with [`set_thread_backend`] for the current one.
+//!
+//! [`Invocation`]: struct.Invocation.html
+//! [`Backend`]: trait.Backend.html
+//! [`Golem`]: struct.Golem.html
+//! [`Local`]: struct.Local.html
+//! [`set_backend`]: fn.set_backend.html
+//! [`set_thread_backend`]: fn.set_thread_backend.html
+use crate::{
+    config::{BackendKind, Config},
+    package::{CachedPackage, Compression, Package, PackageCache},
+    Error,
+};
+use anyhow::{anyhow, Context};
+use futures::{
+    channel::mpsc,
+    future::{self, FutureExt, LocalBoxFuture},
+    stream::{self, FuturesUnordered, LocalBoxStream, StreamExt, TryStreamExt},
+};
+use lazy_static::lazy_static;
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    env, fmt, fs,
+    future::Future,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, PoisonError, RwLock},
+    time::{Duration, Instant},
+};
+use tempfile::TempDir;
+use tokio::{task, time};
+use ya_agreement_utils::{constraints, ConstraintKey, Constraints};
+use yarapi::{
+    commands,
+    requestor::{self, CommandList, Image::Wasm, Requestor},
+};
+
+/// Time a Yagna requestor is given past the timeout of its tasks to settle the payments and
+/// release the allocation, before it's abandoned.
+const SETTLE_TIMEOUT: Duration = Duration::from_secs(5 * 60);
+
+lazy_static! {
+    /// Backend installed with [`set_backend`] for all threads.
+    ///
+    /// [`set_backend`]: fn.set_backend.html
+    static ref BACKEND: RwLock<Option<Arc<dyn Backend + Send + Sync>>> = RwLock::new(None);
+    /// Serializes setting the environment variables `yarapi` reads its settings from.
+    static ref YAGNA_ENV: Mutex<()> = Mutex::new(());
+}
+
+thread_local! {
+    /// Backend installed with [`set_thread_backend`] for the current thread.
+    ///
+    /// [`set_thread_backend`]: fn.set_thread_backend.html
+    static THREAD_BACKEND: RefCell<Option<Arc<dyn Backend>>> = RefCell::new(None);
+    /// Digests and URLs of the packages published by [`Golem`], by their hash. The packages
+    /// are served by the current process, hence aren't shared with other ones.
+    ///
+    /// [`Golem`]: struct.Golem.html
+    static PUBLISHED: RefCell<HashMap<String, (String, String)>> = RefCell::new(HashMap::new());
+}
+
+/// Installs `backend` as the backend used by all `gfaas::remote_fn`-annotated functions,
+/// called from any thread.
+///
+/// This takes precedence over both the backend selected in the runtime [`Config`] and the
+/// default one selected via `run_local` attribute, while a backend installed with
+/// [`set_thread_backend`] takes precedence over this one on its thread.
+///
+/// Note that the futures returned by the annotated functions aren't `Send`, whichever the
+/// backend, since [`Backend`] runs the invocations as `LocalBoxFuture`s. Await them on the
+/// thread which created them, for instance within a `tokio::task::LocalSet`, rather than
+/// spawning them onto a multi-threaded executor.
+///
+/// [`Config`]: ../config/struct.Config.html
+/// [`set_thread_backend`]: fn.set_thread_backend.html
+/// [`Backend`]: trait.Backend.html
+pub fn set_backend<B: Backend + Send + Sync + 'static>(backend: B) {
+    *BACKEND.write().unwrap() = Some(Arc::new(backend));
+}
+
+/// Removes the backend installed with [`set_backend`], if any.
+///
+/// [`set_backend`]: fn.set_backend.html
+pub fn clear_backend() {
+    *BACKEND.write().unwrap() = None;
+}
+
+/// Installs `backend` as the backend used by all `gfaas::remote_fn`-annotated functions called
+/// from the current thread, taking precedence over the one installed with [`set_backend`].
+///
+/// Unlike [`set_backend`], this doesn't require the backend to be `Send` and `Sync`, and it
+/// lets tests running in parallel threads each install their own backend.
+///
+/// [`set_backend`]: fn.set_backend.html
+pub fn set_thread_backend<B: Backend + 'static>(backend: B) {
+    THREAD_BACKEND.with(|b| b.replace(Some(Arc::new(backend))));
+}
+
+/// Removes the backend installed with [`set_thread_backend`] for the current thread, if any.
+///
+/// [`set_thread_backend`]: fn.set_thread_backend.html
+pub fn clear_thread_backend() {
+    THREAD_BACKEND.with(|b| b.replace(None));
+}
+
+/// Runs `invocation` on the current backend, retrying it according to its retry policy.
+///
+/// Retry policy set in the runtime [`Config`] takes precedence over the one set in the
+/// invocation.
+///
+/// [`Config`]: ../config/struct.Config.html
+pub(crate) async fn run(default: BackendKind, invocation: Invocation) -> Result<Vec<u8>, Error> {
+    let config = Config::load().map_err(Error::Config)?;
+    let backend = current(&config, default)?;
+    run_with_retries(&*backend, configure(&config, invocation)).await
+}
+
+/// Runs `invocations` on the current backend as a single batch, retrying the whole batch
+/// according to the retry policy of its invocations.
+pub(crate) async fn run_batch(
+    default: BackendKind,
+    invocations: Vec<Invocation>,
+) -> Result<Vec<Vec<u8>>, Error> {
+    let config = Config::load().map_err(Error::Config)?;
+    let backend = current(&config, default)?;
+    let invocations: Vec<_> = invocations
+        .into_iter()
+        .map(|invocation| configure(&config, invocation))
+        .collect();
+    let (retries, backoff) = match invocations.first() {
+        Some(invocation) => (invocation.retries(), invocation.backoff()),
+        None => return Ok(vec![]),
+    };
+    retry(retries, backoff, || backend.run_batch(invocations.clone())).await
+}
+
+/// Runs `invocations` on the current backend as a single batch, yielding their outputs as
+/// they complete. Unlike [`run_batch`], the batch is never retried, since some of its outputs
+/// might have been yielded already.
+///
+/// [`run_batch`]: fn.run_batch.html
+pub(crate) fn run_streaming(
+    default: BackendKind,
+    invocations: Vec<Invocation>,
+) -> LocalBoxStream<'static, Result<Completed<Vec<u8>>, Error>> {
+    let backend = match Config::load()
+        .map_err(Error::Config)
+        .and_then(|config| current(&config, default))
+    {
+        Ok(backend) => backend,
+        Err(err) => return stream::once(future::ready(Err(err))).boxed_local(),
+    };
+    // The stream returned by the backend borrows it, so it's forwarded through a channel
+    // by a future owning the backend.
+    let (tx, rx) = mpsc::unbounded();
+    let forward = async move {
+        let mut outputs = backend.run_streaming(invocations);
+        while let Some(output) = outputs.next().await {
+            if tx.unbounded_send(output).is_err() {
+                break;
+            }
+        }
+    };
+    stream::select(
+        rx,
+        forward.into_stream().filter_map(|()| future::ready(None)),
+    )
+    .boxed_local()
+}
+
+/// Runs `invocation` on `backend`, retrying it up to [`Invocation::retries`] times if it fails
+/// with a transient error. The delay between consecutive attempts starts at
+/// [`Invocation::backoff`] and doubles with every retry.
+///
+/// Providers which failed aren't excluded from the subsequent attempts, since the backends
+/// don't report which provider a failure came from.
+///
+/// [`Invocation::retries`]: struct.Invocation.html#method.retries
+/// [`Invocation::backoff`]: struct.Invocation.html#method.backoff
+pub async fn run_with_retries(
+    backend: &dyn Backend,
+    invocation: Invocation,
+) -> Result<Vec<u8>, Error> {
+    retry(invocation.retries(), invocation.backoff(), || {
+        backend.run(invocation.clone())
+    })
+    .await
+}
+
+async fn retry<'a, T, F>(retries: u32, backoff: Duration, mut f: F) -> Result<T, Error>
+where
+    F: FnMut() -> LocalBoxFuture<'a, Result<T, Error>>,
+{
+    let mut backoff = backoff;
+    for _ in 0..retries {
+        match f().await {
+            Err(err) if err.is_transient() => {
+                log::warn!("{:#}, retrying in {:?}", anyhow!(err), backoff);
+                time::delay_for(backoff).await;
+                backoff *= 2;
+            }
+            res => return res,
+        }
+    }
+    f().await
+}
+
+/// Applies the retry policy and the package compression set in the runtime `config` to
+/// `invocation`.
+fn configure(config: &Config, mut invocation: Invocation) -> Invocation {
+    if let Some(retries) = config.retries() {
+        invocation = invocation.with_retries(retries);
+    }
+    if let Some(backoff) = config.backoff() {
+        invocation = invocation.with_backoff(backoff);
+    }
+    if let Some(compression) = config.compression() {
+        invocation = invocation.with_compression(compression);
+    }
+    invocation
+}
+
+/// Returns the backend installed for the current thread, or the one installed for all threads,
+/// or the one selected by the runtime [`Config`], falling back to `default` if none is present.
+///
+/// [`Config`]: ../config/struct.Config.html
+fn current(config: &Config, default: BackendKind) -> Result<Arc<dyn Backend>, Error> {
+    if let Some(backend) = THREAD_BACKEND.with(|b| b.borrow().clone()) {
+        return Ok(backend);
+    }
+    if let Some(backend) = BACKEND.read().unwrap().clone() {
+        return Ok(backend);
+    }
+    let backend: Arc<dyn Backend> = match config.backend().unwrap_or(default) {
+        BackendKind::Local => Arc::new(Local),
+        BackendKind::Golem => Arc::new(Golem),
+        #[cfg(feature = "testing")]
+        BackendKind::Mock => Arc::new(crate::testing::MockBackend::new()),
+        #[cfg(not(feature = "testing"))]
+        BackendKind::Mock => {
+            return Err(Error::Config(anyhow!(
+                "mock backend requires 'testing' feature of gfaas"
+            )))
+        }
+    };
+    Ok(backend)
+}
+
+/// Natively compiled function taking serialized inputs and returning serialized output.
+#[cfg(feature = "testing")]
+pub type NativeFn = fn(Vec<Vec<u8>>) -> anyhow::Result<Vec<u8>>;
+
+/// Contents of a Wasm module embedded into the binary, which are left out of `Debug` output.
+#[derive(Clone, Copy)]
+struct EmbeddedModule(&'static [u8]);
+
+impl fmt::Debug for EmbeddedModule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "EmbeddedModule({} bytes)", self.0.len())
+    }
+}
+
+/// Describes a single invocation of a remote function.
+#[derive(Debug, Clone)]
+pub struct Invocation {
+    module_name: String,
+    module: Option<EmbeddedModule>,
+    inputs: Vec<Vec<u8>>,
+    budget: u64,
+    timeout: Duration,
+    subnet: String,
+    retries: u32,
+    backoff: Duration,
+    min_mem_gib: f64,
+    min_storage_gib: f64,
+    min_cpu_threads: Option<u32>,
+    constraints: Vec<Constraint>,
+    datadir: Option<PathBuf>,
+    compression: Compression,
+    app_key: Option<String>,
+    api_url: Option<String>,
+    #[cfg(feature = "testing")]
+    native: Option<NativeFn>,
+}
+
+impl Invocation {
+    /// Creates new invocation of the Wasm module called `module_name`.
+    pub fn new<S: Into<String>>(module_name: S) -> Self {
+        Self {
+            module_name: module_name.into(),
+            module: None,
+            inputs: Vec::new(),
+            budget: 100,
+            timeout: Duration::from_secs(10 * 60),
+            subnet: "devnet-alpha.2".to_owned(),
+            retries: 0,
+            backoff: Duration::from_secs(1),
+            min_mem_gib: 0.5,
+            min_storage_gib: 1.0,
+            min_cpu_threads: None,
+            constraints: Vec::new(),
+            datadir: None,
+            compression: Compression::Stored,
+            app_key: None,
+            api_url: None,
+            #[cfg(feature = "testing")]
+            native: None,
+        }
+    }
+
+    /// Sets the contents of the Wasm module, such as embedded with `include_bytes!`, in place
+    /// of looking the module up next to the current executable.
+    pub fn with_module(mut self, module: &'static [u8]) -> Self {
+        self.module = Some(EmbeddedModule(module));
+        self
+    }
+
+    /// Appends serialized input argument.
+    pub fn with_input(mut self, input: Vec<u8>) -> Self {
+        self.inputs.push(input);
+        self
+    }
+
+    /// Sets the (maximum) budget in NGNT.
+    pub fn with_budget(mut self, budget: u64) -> Self {
+        self.budget = budget;
+        self
+    }
+
+    /// Sets the timeout.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Sets the subnet tag.
+    pub fn with_subnet<S: Into<String>>(mut self, subnet: S) -> Self {
+        self.subnet = subnet.into();
+        self
+    }
+
+    /// Sets the number of times the invocation is retried after a transient failure.
+    pub fn with_retries(mut self, retries: u32) -> Self {
+        self.retries = retries;
+        self
+    }
+
+    /// Sets the delay before the first retry. The delay doubles with every retry.
+    pub fn with_backoff(mut self, backoff: Duration) -> Self {
+        self.backoff = backoff;
+        self
+    }
+
+    /// Requires providers to offer more than `min_mem_gib` GiB of memory.
+    pub fn with_min_mem_gib(mut self, min_mem_gib: f64) -> Self {
+        self.min_mem_gib = min_mem_gib;
+        self
+    }
+
+    /// Requires providers to offer more than `min_storage_gib` GiB of storage.
+    pub fn with_min_storage_gib(mut self, min_storage_gib: f64) -> Self {
+        self.min_storage_gib = min_storage_gib;
+        self
+    }
+
+    /// Requires providers to offer at least `min_cpu_threads` CPU threads.
+    pub fn with_min_cpu_threads(mut self, min_cpu_threads: u32) -> Self {
+        self.min_cpu_threads = Some(min_cpu_threads);
+        self
+    }
+
+    /// Adds a custom constraint on the offer's property `key`.
+    pub fn with_constraint<K, V>(mut self, key: K, op: ConstraintOp, value: V) -> Self
+    where
+        K: Into<String>,
+        V: Into<serde_json::Value>,
+    {
+        self.constraints.push(Constraint {
+            key: key.into(),
+            op,
+            value: value.into(),
+        });
+        self
+    }
+
+    /// Sets the directory in which the workspace of the invocation is created.
+    pub fn with_datadir<P: Into<PathBuf>>(mut self, datadir: P) -> Self {
+        self.datadir = Some(datadir.into());
+        self
+    }
+
+    /// Sets the compression of the Yagna package containing the Wasm module.
+    pub fn with_compression(mut self, compression: Compression) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    /// Sets the app key used to authenticate with the Yagna daemon.
+    ///
+    /// Since `yarapi` reads the app key from `YAGNA_APPKEY` environment variable only, [`Golem`]
+    /// sets the variable for the process while it starts running the tasks, restoring the
+    /// previous value right after. Starting the runs is serialized across threads, but any other
+    /// code reading the variable concurrently may observe the app key.
+    ///
+    /// [`Golem`]: struct.Golem.html
+    pub fn with_app_key<S: Into<String>>(mut self, app_key: S) -> Self {
+        self.app_key = Some(app_key.into());
+        self
+    }
+
+    /// Sets the URL of the Yagna daemon's REST API.
+    ///
+    /// As with [`with_app_key`], [`Golem`] passes the URL to `yarapi` by setting
+    /// `YAGNA_API_URL` environment variable while it starts running the tasks.
+    ///
+    /// [`with_app_key`]: #method.with_app_key
+    /// [`Golem`]: struct.Golem.html
+    pub fn with_api_url<S: Into<String>>(mut self, api_url: S) -> Self {
+        self.api_url = Some(api_url.into());
+        self
+    }
+
+    /// Sets the natively compiled version of the function.
+    #[cfg(feature = "testing")]
+    pub fn with_native(mut self, native: NativeFn) -> Self {
+        self.native = Some(native);
+        self
+    }
+
+    /// Name of the Wasm module to invoke.
+    pub fn module_name(&self) -> &str {
+        &self.module_name
+    }
+
+    /// Contents of the Wasm module, if set.
+    pub fn module(&self) -> Option<&'static [u8]> {
+        self.module.map(|module| module.0)
+    }
+
+    /// Serialized input arguments in order.
+    pub fn inputs(&self) -> &[Vec<u8>] {
+        &self.inputs
+    }
+
+    /// (Maximum) budget in NGNT.
+    pub fn budget(&self) -> u64 {
+        self.budget
+    }
+
+    /// Timeout of the invocation.
+    pub fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    /// Subnet tag.
+    pub fn subnet(&self) -> &str {
+        &self.subnet
+    }
+
+    /// Number of times the invocation is retried after a transient failure.
+    pub fn retries(&self) -> u32 {
+        self.retries
+    }
+
+    /// Delay before the first retry.
+    pub fn backoff(&self) -> Duration {
+        self.backoff
+    }
+
+    /// Minimum memory in GiB required from providers.
+    pub fn min_mem_gib(&self) -> f64 {
+        self.min_mem_gib
+    }
+
+    /// Minimum storage in GiB required from providers.
+    pub fn min_storage_gib(&self) -> f64 {
+        self.min_storage_gib
+    }
+
+    /// Minimum number of CPU threads required from providers, if any.
+    pub fn min_cpu_threads(&self) -> Option<u32> {
+        self.min_cpu_threads
+    }
+
+    /// Custom constraints on the offers of providers.
+    pub fn constraints(&self) -> &[Constraint] {
+        &self.constraints
+    }
+
+    /// Directory in which the workspace of the invocation is created, if any.
+    pub fn datadir(&self) -> Option<&Path> {
+        self.datadir.as_deref()
+    }
+
+    /// Compression of the Yagna package containing the Wasm module.
+    pub fn compression(&self) -> Compression {
+        self.compression
+    }
+
+    /// App key used to authenticate with the Yagna daemon, if any.
+    pub fn app_key(&self) -> Option<&str> {
+        self.app_key.as_deref()
+    }
+
+    /// URL of the Yagna daemon's REST API, if any.
+    pub fn api_url(&self) -> Option<&str> {
+        self.api_url.as_deref()
+    }
+
+    /// Natively compiled version of the function, if any.
+    #[cfg(feature = "testing")]
+    pub fn native(&self) -> Option<NativeFn> {
+        self.native
+    }
+
+    /// Creates temporary workspace for the invocation, within its datadir if set, or the
+    /// system's temp dir otherwise. The workspace is removed once dropped.
+    pub fn create_workspace(&self) -> Result<TempDir, Error> {
+        let workspace = match &self.datadir {
+            Some(datadir) => fs::create_dir_all(datadir)
+                .and_then(|_| TempDir::new_in(datadir))
+                .with_context(|| format!("creating temp dir in '{}'", datadir.display())),
+            None => TempDir::new().context("creating temp dir"),
+        };
+        workspace.map_err(Error::Package)
+    }
+
+    /// Writes the inputs to a single file at `path`, in the format expected by the Wasm
+    /// module generated by `gfaas::remote_fn`.
+    ///
+    /// Each input is prefixed with its length encoded as little-endian `u64`.
+    pub fn write_inputs<P: AsRef<Path>>(&self, path: P) -> Result<(), Error> {
+        let mut contents = vec![];
+        for input in &self.inputs {
+            contents.extend_from_slice(&(input.len() as u64).to_le_bytes());
+            contents.extend_from_slice(input);
+        }
+        fs::write(path.as_ref(), contents)
+            .context("writing serialized data to file")
+            .map_err(Error::Upload)?;
+        Ok(())
+    }
+
+    /// Cache of Yagna packages used by the invocation, in `packages` subdirectory of its
+    /// datadir if set, or of `gfaas` directory in the user's cache dir otherwise (such as
+    /// `~/.cache` on Linux), falling back to the system's temp dir if there's none.
+    ///
+    /// Cached packages are never evicted; remove the directory to reclaim the space.
+    pub fn package_cache(&self) -> PackageCache {
+        let dir = match &self.datadir {
+            Some(datadir) => datadir.join("packages"),
+            None => dirs::cache_dir()
+                .unwrap_or_else(env::temp_dir)
+                .join("gfaas")
+                .join("packages"),
+        };
+        PackageCache::new(dir)
+    }
+
+    /// Creates Yagna package at `path` containing the invoked Wasm module.
+    ///
+    /// Unless its contents are set, the module is expected to be found next to the current
+    /// executable, which is where `gfaas` build tool puts it.
+    pub fn write_package<P: AsRef<Path>>(&self, path: P) -> Result<(), Error> {
+        self.package()?
+            .write(path.as_ref())
+            .context("saving Yagna zip package to file")
+            .map_err(Error::Package)
+    }
+
+    /// Looks up Yagna package containing the invoked Wasm module in the [`package_cache`],
+    /// writing it there unless cached already.
+    ///
+    /// [`package_cache`]: #method.package_cache
+    pub fn cached_package(&self) -> Result<CachedPackage, Error> {
+        let cache = self.package_cache();
+        cache
+            .insert(self.package()?)
+            .with_context(|| format!("caching Yagna package in '{}'", cache.dir().display()))
+            .map_err(Error::Package)
+    }
+
+    /// Creates Yagna package containing the invoked Wasm module.
+    fn package(&self) -> Result<Package, Error> {
+        let mut package = Package::new();
+        package.set_compression(self.compression);
+        match self.module {
+            Some(EmbeddedModule(module)) => package
+                .add_module_from_bytes(format!("{}.wasm", self.module_name), module)
+                .context("adding embedded Wasm module")
+                .map_err(Error::Package)?,
+            None => {
+                let exe_path = env::current_exe()
+                    .context("extracting path to the current exe")
+                    .map_err(Error::Package)?;
+                let parent = exe_path.parent().ok_or_else(|| {
+                    Error::Package(anyhow!(
+                        "path to the current exe without parent: '{}'",
+                        exe_path.display()
+                    ))
+                })?;
+                let wasm = parent.join(format!("{}.wasm", self.module_name));
+                if !wasm.is_file() {
+                    return Err(Error::MissingModule(wasm));
+                }
+                package
+                    .add_module_from_path(wasm)
+                    .context("adding Wasm module from path")
+                    .map_err(Error::Package)?;
+            }
+        }
+        Ok(package)
+    }
+}
+
+/// Comparison operator of a [`Constraint`].
+///
+/// [`Constraint`]: struct.Constraint.html
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConstraintOp {
+    /// `==`
+    Equal,
+    /// `!=`
+    NotEqual,
+    /// `<`
+    LessThan,
+    /// `>`
+    GreaterThan,
+}
+
+/// Custom constraint on a property of the offers of providers, such as
+/// `golem.runtime.name == wasmtime`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Constraint {
+    /// Name of the property.
+    pub key: String,
+    /// Comparison operator.
+    pub op: ConstraintOp,
+    /// Value the property is compared with.
+    pub value: serde_json::Value,
+}
+
+/// Backend capable of executing an [`Invocation`].
+///
+/// An implementation is expected to package the Wasm module, upload the inputs, run the module
+/// with the input file and the output file as arguments, and finally download the output.
+///
+/// [`Invocation`]: struct.Invocation.html
+pub trait Backend {
+    /// Executes `invocation` returning the serialized output of the function.
+    fn run<'a>(&'a self, invocation: Invocation) -> LocalBoxFuture<'a, Result<Vec<u8>, Error>>;
+
+    /// Executes `invocations` of the same function as a single batch, returning their
+    /// serialized outputs in order.
+    ///
+    /// The default implementation collects the outputs yielded by [`run_streaming`].
+    ///
+    /// [`run_streaming`]: #method.run_streaming
+    fn run_batch<'a>(
+        &'a self,
+        invocations: Vec<Invocation>,
+    ) -> LocalBoxFuture<'a, Result<Vec<Vec<u8>>, Error>> {
+        self.run_streaming(invocations)
+            .try_collect()
+            .map(|outputs: Result<Vec<_>, Error>| {
+                let mut outputs = outputs?;
+                outputs.sort_by_key(|completed| completed.index);
+                Ok(outputs
+                    .into_iter()
+                    .map(|completed| completed.output)
+                    .collect())
+            })
+            .boxed_local()
+    }
+
+    /// Executes `invocations` of the same function as a single batch, yielding their serialized
+    /// outputs as they complete.
+    ///
+    /// The default implementation executes each invocation with [`run`] concurrently.
+    ///
+    /// [`run`]: #tymethod.run
+    fn run_streaming<'a>(
+        &'a self,
+        invocations: Vec<Invocation>,
+    ) -> LocalBoxStream<'a, Result<Completed<Vec<u8>>, Error>> {
+        invocations
+            .into_iter()
+            .enumerate()
+            .map(|(index, invocation)| {
+                self.run(invocation).map(move |output| {
+                    Ok(Completed {
+                        index,
+                        activity_id: None,
+                        output: output?,
+                    })
+                })
+            })
+            .collect::<FuturesUnordered<_>>()
+            .boxed_local()
+    }
+}
+
+/// Output of a single invocation of a batch, yielded as soon as the invocation completes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Completed<T> {
+    /// Index of the invocation within the batch.
+    pub index: usize,
+    /// Id of the Yagna activity which ran the invocation, if the backend runs invocations
+    /// as activities.
+    pub activity_id: Option<String>,
+    /// Output of the invocation.
+    pub output: T,
+}
+
+/// Backend executing functions on the Golem Network.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Golem;
+
+impl Backend for Golem {
+    fn run<'a>(&'a self, invocation: Invocation) -> LocalBoxFuture<'a, Result<Vec<u8>, Error>> {
+        self.run_batch(vec![invocation])
+            .map(|outputs| Ok(outputs?.remove(0)))
+            .boxed_local()
+    }
+
+    /// Executes all `invocations` as separate tasks of a single Yagna requestor, so that
+    /// they share the package and the market negotiation.
+    ///
+    /// Since the requestor reports completed activities but not the tasks they ran, the Wasm
+    /// module reports the index of its task and the size of its output upon completion, so that
+    /// each output is yielded only once its own task completes, and in its entirety.
+    fn run_streaming<'a>(
+        &'a self,
+        invocations: Vec<Invocation>,
+    ) -> LocalBoxStream<'a, Result<Completed<Vec<u8>>, Error>> {
+        enum Event {
+            Completed(String, Vec<String>),
+            Finished(Result<(), Error>),
+        }
+
+        let first = match invocations.first() {
+            Some(invocation) => invocation,
+            None => return stream::empty().boxed_local(),
+        };
+        if let Some(other) = invocations
+            .iter()
+            .find(|invocation| invocation.module_name() != first.module_name())
+        {
+            let err = Error::Package(anyhow!(
+                "batch mixes invocations of '{}' and '{}'",
+                first.module_name(),
+                other.module_name()
+            ));
+            return stream::once(future::ready(Err(err))).boxed_local();
+        }
+
+        // 1. Create temp workspace
+        let workspace = match first.create_workspace() {
+            Ok(workspace) => workspace,
+            Err(err) => return stream::once(future::ready(Err(err))).boxed_local(),
+        };
+
+        // 2. Prepare package
+        let package = match first.cached_package() {
+            Ok(package) => package,
+            Err(err) => return stream::once(future::ready(Err(err))).boxed_local(),
+        };
+
+        // 3. Prepare workspace
+        let mut pending = vec![];
+        let mut tasks = vec![];
+        for (i, invocation) in invocations.iter().enumerate() {
+            let input_path = workspace.path().join(format!("in{}", i));
+            let output_path = workspace.path().join(format!("out{}", i));
+            if let Err(err) = invocation.write_inputs(&input_path) {
+                return stream::once(future::ready(Err(err))).boxed_local();
+            }
+
+            let module_name = invocation.module_name();
+            let task = i.to_string();
+            tasks.push(commands! {
+                upload(input_path, "/workdir/in");
+                run(module_name, task, "/workdir/in", "/workdir/out");
+                download("/workdir/out", &output_path);
+            });
+            pending.push((i, output_path));
+        }
+
+        // 4. Run
+        // `yarapi` only picks up the app key and the API URL from the environment.
+        let mut vars = vec![];
+        if let Some(app_key) = first.app_key() {
+            vars.push(("YAGNA_APPKEY", app_key.to_owned()));
+        }
+        if let Some(api_url) = first.api_url() {
+            vars.push(("YAGNA_API_URL", api_url.to_owned()));
+        }
+        if first.app_key().is_none() && env::var_os("YAGNA_APPKEY").is_none() {
+            let err = Error::Backend(anyhow!(
+                "missing Yagna app key: set 'app_key' attribute or YAGNA_APPKEY env variable"
+            ));
+            return stream::once(future::ready(Err(err))).boxed_local();
+        }
+        let budget = invocations.iter().map(Invocation::budget).sum();
+        let timeout = invocations
+            .iter()
+            .map(Invocation::timeout)
+            .max()
+            .unwrap_or_default();
+        let (tx, rx) = mpsc::unbounded();
+        let completed_tx = tx.clone();
+        let subnet = first.subnet().to_owned();
+        let constraints = provider_constraints(first);
+        let requestor_run = async move {
+            let package = publish(&package).await?;
+            let requestor = Requestor::new("custom", Wasm((0, 0, 0).into()), package)
+                .with_subnet(subnet)
+                .with_max_budget_ngnt(budget)
+                .with_timeout(timeout)
+                .with_constraints(constraints)
+                .with_tasks(tasks.into_iter())
+                .on_completed(move |activity_id, output| {
+                    log::debug!("activity [{}] completed: {:?}", activity_id, output);
+                    let _ = completed_tx.unbounded_send(Event::Completed(activity_id, output));
+                });
+            with_yagna_env(vars, requestor.run())
+                .await
+                .context("running task on Yagna")
+                .map_err(run_error)
+        };
+        // `yarapi` stops computing the tasks itself once the timeout elapses, and then still
+        // settles the payments, so it's only abandoned if that hangs.
+        let started = Instant::now();
+        let run = async move {
+            let res = time::timeout(timeout + SETTLE_TIMEOUT, requestor_run)
+                .await
+                .map_err(|_| Error::Timeout(timeout))
+                .and_then(|res| res);
+            let _ = tx.unbounded_send(Event::Finished(res));
+        };
+
+        // 5. Collect the results as they arrive
+        let events = stream::select(rx, run.into_stream().filter_map(|()| future::ready(None)));
+        events
+            .scan((workspace, pending), move |(_, pending), event| {
+                let outputs = match event {
+                    Event::Completed(activity_id, output) => {
+                        match completed_task(&output, pending) {
+                            Some((index, path, len)) => {
+                                vec![read_output(index, &path, len, activity_id)]
+                            }
+                            None => {
+                                log::warn!("activity [{}] completed no pending task", activity_id);
+                                vec![]
+                            }
+                        }
+                    }
+                    Event::Finished(Ok(())) => {
+                        // `yarapi` finishes without an error when it times out.
+                        let timed_out = started.elapsed() >= timeout;
+                        pending
+                            .drain(..)
+                            .map(|(i, _)| {
+                                if timed_out {
+                                    Err(Error::Timeout(timeout))
+                                } else {
+                                    Err(Error::Download(anyhow!("missing output of task {}", i)))
+                                }
+                            })
+                            .collect()
+                    }
+                    Event::Finished(Err(err)) => vec![Err(err)],
+                };
+                future::ready(Some(stream::iter(outputs)))
+            })
+            .flatten()
+            .boxed_local()
+    }
+}
+
+/// Publishes the cached `package` for the providers to download, unless it has already been
+/// published by the current process, in which case it's referred to by its URL.
+async fn publish(package: &CachedPackage) -> Result<requestor::Package, Error> {
+    let published = PUBLISHED.with(|published| published.borrow().get(package.hash()).cloned());
+    let (digest, url) = match published {
+        Some(published) => published,
+        None => {
+            let (digest, url) = requestor::Package::Archive(package.path().to_owned())
+                .publish()
+                .await
+                .context("publishing Yagna package")
+                .map_err(Error::Package)?;
+            let published = (digest, url.to_string());
+            PUBLISHED.with(|p| {
+                p.borrow_mut()
+                    .insert(package.hash().to_owned(), published.clone())
+            });
+            published
+        }
+    };
+    Ok(requestor::Package::Url { digest, url })
+}
+
+/// Runs `requestor_run` with environment variables `vars` set while it's polled for the first
+/// time, restoring their previous values afterwards.
+///
+/// `yarapi` reads its settings from the environment when it starts running the tasks, that is
+/// before its future first yields, hence the variables need to be set just that long. Setting
+/// them is serialized across threads so that concurrent runs don't observe each other's.
+async fn with_yagna_env<F: Future>(
+    vars: Vec<(&'static str, String)>,
+    requestor_run: F,
+) -> F::Output {
+    let mut requestor_run = Box::pin(requestor_run);
+    let mut vars = Some(vars);
+    future::poll_fn(move |cx| {
+        let vars = match vars.take() {
+            Some(vars) => vars,
+            None => return requestor_run.as_mut().poll(cx),
+        };
+        let _lock = YAGNA_ENV.lock().unwrap_or_else(PoisonError::into_inner);
+        let previous: Vec<_> = vars
+            .into_iter()
+            .map(|(key, value)| {
+                let previous = env::var_os(key);
+                env::set_var(key, value);
+                (key, previous)
+            })
+            .collect();
+        let poll = requestor_run.as_mut().poll(cx);
+        for (key, previous) in previous {
+            match previous {
+                Some(value) => env::set_var(key, value),
+                None => env::remove_var(key),
+            }
+        }
+        poll
+    })
+    .await
+}
+
+/// Wraps the failure of running tasks on Yagna, telling the app key being rejected by the Yagna
+/// daemon, which isn't worth retrying, apart from the failures of the providers.
+fn run_error(err: anyhow::Error) -> Error {
+    let rejected = err
+        .chain()
+        .any(|cause| match cause.downcast_ref::<ya_client::Error>() {
+            Some(ya_client::Error::HttpStatusCode { code, .. }) => {
+                code.as_u16() == 401 || code.as_u16() == 403
+            }
+            _ => false,
+        });
+    if rejected {
+        Error::Backend(err)
+    } else {
+        Error::Run(err)
+    }
+}
+
+/// Builds the constraints on the offers of providers required by `invocation`.
+fn provider_constraints(invocation: &Invocation) -> Constraints {
+    let mut constraints = constraints![
+        "golem.inf.mem.gib" > invocation.min_mem_gib(),
+        "golem.inf.storage.gib" > invocation.min_storage_gib(),
+    ];
+    if let Some(threads) = invocation.min_cpu_threads().filter(|&threads| threads > 0) {
+        constraints = constraints.and(constraints!["golem.inf.cpu.threads" > threads - 1]);
+    }
+    for constraint in invocation.constraints() {
+        let key = ConstraintKey::new(constraint.key.as_str());
+        let value = ConstraintKey::new(constraint.value.clone());
+        let expr = match constraint.op {
+            ConstraintOp::Equal => key.equal_to(value),
+            ConstraintOp::NotEqual => key.not_equal_to(value),
+            ConstraintOp::LessThan => key.less_than(value),
+            ConstraintOp::GreaterThan => key.greater_than(value),
+        };
+        constraints = constraints.and(Constraints::new_single(expr));
+    }
+    constraints
+}
+
+/// Removes the task reported as completed by the run `output` of an activity from `pending`,
+/// returning its index, the path its output is downloaded to and the size of the output.
+///
+/// The Wasm module prints `gfaas-task <index> <size>` when run as a task of a batch.
+fn completed_task(
+    output: &[String],
+    pending: &mut Vec<(usize, PathBuf)>,
+) -> Option<(usize, PathBuf, u64)> {
+    let (index, len): (usize, u64) =
+        output
+            .iter()
+            .flat_map(|message| message.lines())
+            .find_map(|line| {
+                let mut words = line.trim().strip_prefix("gfaas-task ")?.split(' ');
+                Some((words.next()?.parse().ok()?, words.next()?.parse().ok()?))
+            })?;
+    let position = pending.iter().position(|(i, _)| *i == index)?;
+    let (index, path) = pending.remove(position);
+    Some((index, path, len))
+}
+
+/// Reads the output of a completed task, checking that it has been downloaded entirely.
+fn read_output(
+    index: usize,
+    path: &Path,
+    len: u64,
+    activity_id: String,
+) -> Result<Completed<Vec<u8>>, Error> {
+    let output = fs::read(path)
+        .context("reading output data from file")
+        .map_err(Error::Download)?;
+    if output.len() as u64 != len {
+        return Err(Error::Download(anyhow!(
+            "output of task {} is {} bytes long, expected {}",
+            index,
+            output.len(),
+            len
+        )));
+    }
+    Ok(Completed {
+        index,
+        activity_id: Some(activity_id),
+        output,
+    })
+}
+
+/// Backend executing functions locally with `ya-runtime-wasi`, each in a separate thread.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Local;
+
+impl Backend for Local {
+    fn run<'a>(&'a self, invocation: Invocation) -> LocalBoxFuture<'a, Result<Vec<u8>, Error>> {
+        async move {
+            task::spawn_blocking(move || {
+                // 0. Create temp workspace
+                let workspace = invocation.create_workspace()?;
+
+                // 1. Prepare zip archive
+                let package = invocation.cached_package()?;
+
+                // 2. Deploy
+                ya_runtime_wasi::deploy(workspace.path(), package.path())
+                    .context("deploying Yagna package")
+                    .map_err(Error::Deploy)?;
+                ya_runtime_wasi::start(workspace.path())
+                    .context("executing Yagna start command")
+                    .map_err(Error::Deploy)?;
+
+                let deployment = ya_runtime_wasi::DeployFile::load(workspace.path())
+                    .context("loading deployed Yagna package")
+                    .map_err(Error::Deploy)?;
+                let vol: PathBuf = deployment
+                    .vols()
+                    .find(|vol| vol.path.starts_with("/workdir"))
+                    .map(|vol| workspace.path().join(&vol.name))
+                    .context("extracting workdir path from Yagna package")
+                    .map_err(Error::Deploy)?;
+
+                let input_path = vol.join("in");
+                let output_path = vol.join("out");
+                invocation.write_inputs(&input_path)?;
+
+                // 3. Run
+                ya_runtime_wasi::run(
+                    workspace.path(),
+                    invocation.module_name(),
+                    vec!["/workdir/in".to_owned(), "/workdir/out".to_owned()],
+                )
+                .context("executing Yagna run command")
+                .map_err(Error::Run)?;
+
+                // 4. Collect the results
+                let output = fs::read(output_path)
+                    .context("reading output data from file")
+                    .map_err(Error::Download)?;
+                Ok(output)
+            })
+            .await
+            .map_err(|err| Error::Backend(err.into()))?
+        }
+        .boxed_local()
+    }
+}
@@ -0,0 +1,188 @@
+//! Runtime configuration of `gfaas`.
+//!
+//! Configuration is read from `GFAAS_*` environment variables and, with lower precedence,
+//! from a TOML file. The file is looked up at the path given by `GFAAS_CONFIG` environment
+//! variable, or `gfaas.toml` in the current working directory otherwise.
+//!
+//! ```toml
+//! # gfaas.toml
+//! backend = "local"
+//! ```
+//!
+//! The following settings are currently recognized:
+//!
+//! * `backend` (`GFAAS_BACKEND`) -- the backend to run functions on, one of `local`, `golem`
+//!   or `mock` (the latter requires `testing` feature).
+//! * `retries` (`GFAAS_RETRIES`) -- the number of times a call is retried after a transient
+//!   failure, overriding `retries` attribute of `gfaas::remote_fn`.
+//! * `backoff` (`GFAAS_BACKOFF`) -- the delay in seconds before the first retry, overriding
+//!   `backoff` attribute of `gfaas::remote_fn`.
+//! * `compression` (`GFAAS_COMPRESSION`) -- the compression of the Yagna packages, either
+//!   `stored` (the default) or `deflate`.
+use crate::package::Compression;
+use anyhow::{anyhow, bail, Context, Result};
+use std::{
+    convert::TryFrom,
+    env, fmt, fs,
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::Duration,
+};
+
+/// Kind of a built-in backend.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BackendKind {
+    /// Run functions locally using `ya-runtime-wasi`.
+    Local,
+    /// Run functions on the Golem Network.
+    Golem,
+    /// Run functions in-process with `gfaas::testing::MockBackend`. Requires `testing`
+    /// feature.
+    Mock,
+}
+
+impl FromStr for BackendKind {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "local" => Ok(Self::Local),
+            "golem" => Ok(Self::Golem),
+            "mock" => Ok(Self::Mock),
+            x => bail!(
+                "unknown backend '{}': expected 'local', 'golem' or 'mock'",
+                x
+            ),
+        }
+    }
+}
+
+impl fmt::Display for BackendKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Local => write!(f, "local"),
+            Self::Golem => write!(f, "golem"),
+            Self::Mock => write!(f, "mock"),
+        }
+    }
+}
+
+/// Runtime configuration of `gfaas`.
+#[derive(Debug, Default, Clone)]
+pub struct Config {
+    backend: Option<BackendKind>,
+    retries: Option<u32>,
+    backoff: Option<Duration>,
+    compression: Option<Compression>,
+}
+
+impl Config {
+    /// Loads configuration from the environment and the config file, if any.
+    pub fn load() -> Result<Self> {
+        let mut config = match config_path() {
+            Some(path) => Self::from_file(&path)?,
+            None => Self::default(),
+        };
+        if let Ok(backend) = env::var("GFAAS_BACKEND") {
+            config.backend = Some(
+                backend
+                    .parse()
+                    .context("parsing 'GFAAS_BACKEND' environment variable")?,
+            );
+        }
+        if let Ok(retries) = env::var("GFAAS_RETRIES") {
+            config.retries = Some(
+                retries
+                    .parse()
+                    .context("parsing 'GFAAS_RETRIES' environment variable")?,
+            );
+        }
+        if let Ok(backoff) = env::var("GFAAS_BACKOFF") {
+            let backoff = backoff
+                .parse()
+                .context("parsing 'GFAAS_BACKOFF' environment variable")?;
+            config.backoff = Some(Duration::from_secs(backoff));
+        }
+        if let Ok(compression) = env::var("GFAAS_COMPRESSION") {
+            config.compression = Some(
+                compression
+                    .parse()
+                    .context("parsing 'GFAAS_COMPRESSION' environment variable")?,
+            );
+        }
+        Ok(config)
+    }
+
+    /// Loads configuration from TOML file at `path`.
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let contents = fs::read_to_string(path.as_ref())
+            .with_context(|| format!("failed to read '{}'", path.as_ref().display()))?;
+        let toml = contents
+            .parse::<toml::Value>()
+            .with_context(|| format!("parsing '{}' as TOML", path.as_ref().display()))?;
+        let toml = toml
+            .as_table()
+            .ok_or_else(|| anyhow!("malformed '{}'?", path.as_ref().display()))?;
+
+        let mut config = Self::default();
+        if let Some(backend) = toml.get("backend") {
+            let backend = backend
+                .as_str()
+                .ok_or_else(|| anyhow!("'backend' is not a string"))?;
+            config.backend = Some(backend.parse()?);
+        }
+        if let Some(retries) = toml.get("retries") {
+            let retries = retries
+                .as_integer()
+                .and_then(|x| u32::try_from(x).ok())
+                .ok_or_else(|| anyhow!("'retries' is not a non-negative integer"))?;
+            config.retries = Some(retries);
+        }
+        if let Some(backoff) = toml.get("backoff") {
+            let backoff = backoff
+                .as_integer()
+                .and_then(|x| u64::try_from(x).ok())
+                .ok_or_else(|| anyhow!("'backoff' is not a non-negative integer"))?;
+            config.backoff = Some(Duration::from_secs(backoff));
+        }
+        if let Some(compression) = toml.get("compression") {
+            let compression = compression
+                .as_str()
+                .ok_or_else(|| anyhow!("'compression' is not a string"))?;
+            config.compression = Some(compression.parse()?);
+        }
+        Ok(config)
+    }
+
+    /// Backend to run functions on, if configured.
+    pub fn backend(&self) -> Option<BackendKind> {
+        self.backend
+    }
+
+    /// Number of times a call is retried after a transient failure, if configured.
+    pub fn retries(&self) -> Option<u32> {
+        self.retries
+    }
+
+    /// Delay before the first retry, if configured.
+    pub fn backoff(&self) -> Option<Duration> {
+        self.backoff
+    }
+
+    /// Compression of the Yagna packages, if configured.
+    pub fn compression(&self) -> Option<Compression> {
+        self.compression
+    }
+}
+
+fn config_path() -> Option<PathBuf> {
+    if let Some(path) = env::var_os("GFAAS_CONFIG") {
+        return Some(PathBuf::from(path));
+    }
+    let path = PathBuf::from("gfaas.toml");
+    if path.is_file() {
+        Some(path)
+    } else {
+        None
+    }
+}
@@ -0,0 +1,112 @@
+//! Errors returned by `gfaas::remote_fn`-annotated functions.
+use std::{fmt, path::PathBuf, time::Duration};
+
+/// Error returned by the expanded `gfaas::remote_fn`-annotated function.
+///
+/// The variants correspond to the stages the expanded function goes through, with the
+/// underlying cause available via `std::error::Error::source`.
+#[derive(Debug, thiserror::Error)]
+#[non_exhaustive]
+pub enum Error {
+    /// Loading runtime configuration failed.
+    #[error("loading gfaas config")]
+    Config(#[source] anyhow::Error),
+    /// Serializing input data failed.
+    #[error("serializing input data")]
+    Serialize(#[source] anyhow::Error),
+    /// The compiled Wasm module could not be found.
+    #[error("Wasm module not found at '{}': did you build the project with gfaas tool?", .0.display())]
+    MissingModule(PathBuf),
+    /// A generic function was called with types it wasn't instantiated with.
+    #[error("'{function}' is not instantiated with '{types}': did you list them in 'instantiate' attribute?")]
+    NotInstantiated {
+        /// Name of the function.
+        function: &'static str,
+        /// Types the type parameters of the function were called with.
+        types: &'static str,
+    },
+    /// Creating Yagna package failed.
+    #[error("packaging Wasm module")]
+    Package(#[source] anyhow::Error),
+    /// Deploying Yagna package failed.
+    #[error("deploying Yagna package")]
+    Deploy(#[source] anyhow::Error),
+    /// Uploading input data failed.
+    #[error("uploading input data")]
+    Upload(#[source] anyhow::Error),
+    /// Running the Wasm module failed.
+    #[error("running Wasm module")]
+    Run(#[source] anyhow::Error),
+    /// Running the Wasm module didn't finish in time.
+    #[error("timed out after {0:?}")]
+    Timeout(Duration),
+    /// Downloading output data failed.
+    #[error("downloading output data")]
+    Download(#[source] anyhow::Error),
+    /// Deserializing output data failed.
+    #[error("deserializing output data")]
+    Deserialize(#[source] anyhow::Error),
+    /// The backend failed for reasons not related to the provider, such as a missing or rejected
+    /// Yagna app key, or a panic within the backend.
+    #[error("backend failed")]
+    Backend(#[source] anyhow::Error),
+}
+
+impl Error {
+    /// Returns `true` if the error is likely caused by a transient failure of the provider or
+    /// the network, and so the call is worth retrying.
+    pub fn is_transient(&self) -> bool {
+        matches!(
+            self,
+            Self::Deploy(_) | Self::Upload(_) | Self::Run(_) | Self::Timeout(_) | Self::Download(_)
+        )
+    }
+}
+
+/// Error returned by an expanded `gfaas::remote_fn`-annotated function which itself returns
+/// `Result<T, E>`.
+///
+/// It distinguishes the error `E` returned by the function (after it has been deserialized
+/// back on the caller's side) from any error encountered while running the function.
+#[derive(Debug)]
+pub enum RemoteError<E> {
+    /// The function ran to completion and returned an error.
+    Application(E),
+    /// Running the function failed for reasons not related to the function itself, such as
+    /// network downtime, etc.
+    Infrastructure(Error),
+}
+
+impl<E> RemoteError<E> {
+    /// Returns the error returned by the function, if that's what this error is.
+    pub fn into_application(self) -> Option<E> {
+        match self {
+            Self::Application(err) => Some(err),
+            Self::Infrastructure(_) => None,
+        }
+    }
+}
+
+impl<E> From<Error> for RemoteError<E> {
+    fn from(err: Error) -> Self {
+        Self::Infrastructure(err)
+    }
+}
+
+impl<E: fmt::Display> fmt::Display for RemoteError<E> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Application(err) => write!(f, "remote function returned an error: {}", err),
+            Self::Infrastructure(err) => write!(f, "running remote function failed: {}", err),
+        }
+    }
+}
+
+impl<E: fmt::Debug + fmt::Display> std::error::Error for RemoteError<E> {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Application(_) => None,
+            Self::Infrastructure(err) => Some(err),
+        }
+    }
+}
@@ -0,0 +1,59 @@
+//! Serialization formats of the inputs and output of `gfaas::remote_fn`-annotated functions.
+use anyhow::Result;
+use serde::{de::DeserializeOwned, Serialize};
+use std::fmt;
+
+/// Serialization format of the inputs and output of a remote function, selected with the
+/// `format` attribute of `gfaas::remote_fn`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// JSON via `serde_json` (the default).
+    Json,
+    /// `bincode`.
+    Bincode,
+    /// CBOR via `serde_cbor`.
+    Cbor,
+    /// MessagePack via `rmp-serde`.
+    Msgpack,
+}
+
+impl Default for Format {
+    fn default() -> Self {
+        Self::Json
+    }
+}
+
+impl Format {
+    /// Serializes `value` in this format.
+    pub fn serialize<T: Serialize>(self, value: &T) -> Result<Vec<u8>> {
+        let serialized = match self {
+            Self::Json => serde_json::to_vec(value)?,
+            Self::Bincode => bincode::serialize(value)?,
+            Self::Cbor => serde_cbor::to_vec(value)?,
+            Self::Msgpack => rmp_serde::to_vec_named(value)?,
+        };
+        Ok(serialized)
+    }
+
+    /// Deserializes `data` in this format.
+    pub fn deserialize<T: DeserializeOwned>(self, data: &[u8]) -> Result<T> {
+        let deserialized = match self {
+            Self::Json => serde_json::from_slice(data)?,
+            Self::Bincode => bincode::deserialize(data)?,
+            Self::Cbor => serde_cbor::from_slice(data)?,
+            Self::Msgpack => rmp_serde::from_slice(data)?,
+        };
+        Ok(deserialized)
+    }
+}
+
+impl fmt::Display for Format {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Json => write!(f, "json"),
+            Self::Bincode => write!(f, "bincode"),
+            Self::Cbor => write!(f, "cbor"),
+            Self::Msgpack => write!(f, "msgpack"),
+        }
+    }
+}
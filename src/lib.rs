@@ -71,9 +71,197 @@
 //! run on top of some network of nodes: it may fail due to reasons not related to your app
 //! such as network downtime, etc.
 //!
+//! The returned `gfaas::Error` tells you at which stage running the function failed (e.g.,
+//! packaging, deploying, running, or deserializing the output), so you can decide whether it is
+//! worth retrying.
+//!
 //! Furthermore, the input and output arguments of your function have to be serializable, and
 //! so they are expected to derive `serde::Serialize` and `serde::Deserialize` traits.
 //!
+//! Functions taking no arguments, as well as functions returning nothing (that is, the unit
+//! type `()`), are supported too. The latter expand into async functions returning
+//! `Result<(), gfaas::Error>`.
+//!
+//! Arguments may be borrowed, in which case they are deserialized into their owned equivalents
+//! before being passed to your function: `&str` into `String`, `&[T]` into `Vec<T>`, and `&T`
+//! into `T` in general. Arguments passed by mutable reference are not supported, since any
+//! changes made to them could not be sent back to the caller. Arguments may be destructured too
+//!
+//! ```rust,ignore
+//! #[remote_fn]
+//! fn describe(name: &str, (x, y): (i32, i32)) -> String;
+//! ```
+//!
+//! Other attributes of your function, such as doc comments, are carried over to the expanded
+//! function, while `#[cfg]` attributes apply to the generated Wasm module as well, so that no
+//! module is compiled for a function which is configured out. Async and unsafe functions are not
+//! supported.
+//!
+//! ### Returning errors from your function
+//!
+//! If your function returns `Result<T, E>`, where `E` is serializable too, the error it returns
+//! is propagated back to the caller. The function
+//!
+//! ```rust,ignore
+//! #[remote_fn]
+//! fn parse(input: String) -> Result<u64, String>;
+//! ```
+//!
+//! expands into
+//!
+//! ```rust,ignore
+//! async fn parse(input: String) -> Result<u64, gfaas::RemoteError<String>>;
+//! ```
+//!
+//! where `gfaas::RemoteError::Application` carries the error returned by your function, and
+//! `gfaas::RemoteError::Infrastructure` any error encountered while running it.
+//!
+//! ### Generic functions
+//!
+//! Since every function is compiled into a Wasm module of its own, generic functions need to
+//! be told which types to instantiate them with, using `instantiate` attribute. A Wasm module is
+//! compiled for each of the listed instantiations, and the expanded function runs the one
+//! matching the types it's called with:
+//!
+//! ```rust,ignore
+//! #[remote_fn(instantiate(T = u64, T = f64))]
+//! fn total<T: Serialize + DeserializeOwned + Sum>(values: Vec<T>) -> T;
+//! ```
+//!
+//! Functions with multiple type parameters expect each instantiation in parentheses, e.g.
+//! `instantiate((K = String, V = u64), (K = u64, V = Vec<u8>))`. The type parameters have to be
+//! `'static`, and calling the function with types which weren't listed fails with
+//! `gfaas::Error::NotInstantiated`. Lifetime and const parameters are not supported.
+//!
+//! ### Sharing code between functions
+//!
+//! The body of your function is all its Wasm module gets, and so it can't call helpers defined
+//! elsewhere in your crate. Items which the functions need are instead shared with all of the
+//! Wasm modules using `gfaas::shared` attribute:
+//!
+//! ```rust,ignore
+//! #[gfaas::shared]
+//! use num_complex::Complex;
+//!
+//! #[gfaas::shared]
+//! mod fractal {
+//!     use super::*;
+//!
+//!     pub fn mandelbrot(c: Complex<f64>) -> u32;
+//! }
+//!
+//! #[remote_fn]
+//! fn compute_rectangle(start_y: u32, end_y: u32, width: u32, height: u32) -> Vec<u32> {
+//!     // ...
+//!     output.push(fractal::mandelbrot(c));
+//! }
+//! ```
+//!
+//! Functions, types, traits, constants, statics, impl blocks, use declarations and inline modules
+//! can be shared. Shared items stay in your crate as they are, and are copied into a library
+//! every Wasm module imports everything from. Hence, your function has to refer to them by name
+//! or relative to a shared module, rather than through `crate::` paths, and any crates they use
+//! have to be listed in `[gfaas_dependencies]` (see below). In the Wasm modules, the shared items
+//! themselves, struct fields and items of inherent impls are made public, as are items of shared
+//! modules with restricted visibility, such as `pub(crate)`. Since all of the shared items end up
+//! in that one library, sharing two items of the same kind and name from different modules of
+//! your crate is an error; share a module holding them instead.
+//!
+//! ### Methods
+//!
+//! Methods and associated functions can be annotated too, provided the impl block they belong to
+//! is annotated as well. The receiver is then passed to the Wasm module just like any other
+//! argument, and so it has to be serializable, and taken either by value or by shared reference.
+//! Since the Wasm modules are compiled separately from your crate, the type itself has to be
+//! shared with them as well:
+//!
+//! ```rust,ignore
+//! #[gfaas::shared]
+//! #[derive(serde::Serialize, serde::Deserialize)]
+//! struct Rect {
+//!     width: f64,
+//!     height: f64,
+//! }
+//!
+//! #[remote_fn]
+//! impl Rect {
+//!     #[remote_fn(budget = 10)]
+//!     fn area(&self) -> f64 {
+//!         self.width * self.height
+//!     }
+//! }
+//! ```
+//!
+//! Any paths the shared type refers to have to resolve in the Wasm modules too, and so it's best
+//! to spell them out in full, as in `serde::Serialize` above, with `serde` and its `derive`
+//! feature listed in `[gfaas_dependencies]` (see below). Unlike functions, methods don't get a
+//! companion module for running many invocations at once.
+//!
+//! ### Choosing serialization format
+//!
+//! By default, the inputs and output of your function are serialized as JSON. For functions
+//! exchanging large amounts of data, you can pick a more compact format with `format`
+//! attribute:
+//!
+//! ```rust,ignore
+//! #[remote_fn(format = "bincode")]
+//! fn partial_sum(input: Vec<u64>) -> u64;
+//! ```
+//!
+//! The supported formats are `json`, `bincode`, `cbor` and `msgpack`. The `gfaas` build tool
+//! adds the crate required by the chosen format to your Wasm modules automatically.
+//!
+//! ### Running many invocations at once
+//!
+//! Every call to your function negotiates a new agreement on the Golem Network and uploads
+//! the Wasm module anew. If you need to run the function on many inputs, you can instead use
+//! the generated companion module of the same name as your function, which runs all of the
+//! invocations as separate tasks in a single agreement:
+//!
+//! ```rust,ignore
+//! #[remote_fn]
+//! fn compute_rectangle(start_y: u32, end_y: u32, width: u32, height: u32) -> Vec<u32>;
+//!
+//! let chunks = vec![(0, 200, 600, 400), (200, 400, 600, 400)];
+//! let rects: Vec<Vec<u32>> = compute_rectangle::batch(chunks).await?;
+//! ```
+//!
+//! The outputs are returned in the same order as the inputs. Functions taking multiple arguments
+//! expect them packed in a tuple, while functions taking a single argument expect the argument
+//! itself. Borrowed arguments are expected in their owned form, e.g. `String` in place of
+//! `&str`. The budget of the batch is the sum of budgets of all invocations.
+//!
+//! For functions returning `Result<T, E>`, each output is `Result<T, gfaas::RemoteError<E>>`,
+//! just like the output of a single call, failing on its own if the output can't be
+//! deserialized.
+//!
+//! Since the companion module can't refer to the items declared in a function body, annotated
+//! functions need to be declared outside of function bodies. Note that this is a breaking change
+//! from the versions predating the companion module, which accepted annotated functions within
+//! function bodies. Such functions now fail to compile with
+//!
+//! ```text
+//! error[E0425]: cannot find value `__hello_must_be_declared_outside_of_fn_bodies` in module `super`
+//! ```
+//!
+//! pointing at the name of the function, and need to be moved to module level.
+//!
+//! If you'd rather process the outputs as soon as they arrive, use `stream` instead, which
+//! yields each output as `gfaas::Completed` along with the index of its input and the id of the
+//! activity which computed it:
+//!
+//! ```rust,ignore
+//! use futures::TryStreamExt;
+//!
+//! let mut rects = compute_rectangle::stream(chunks);
+//! while let Some(rect) = rects.try_next().await? {
+//!     println!("chunk {} computed by {:?}", rect.index, rect.activity_id);
+//! }
+//! ```
+//!
+//! Note that a failed batch is retried as a whole when using `batch`, but never when using
+//! `stream`, since some of its outputs might have already been yielded.
+//!
 //! ### Specifying Golem's configuration parameters
 //!
 //! You can currently set the following configuration parameters directly via `gfaas::remote_fn`
@@ -100,6 +288,49 @@
 //! fn hello(input: String) -> String;
 //! ```
 //!
+//! * number of retries after a transient failure, such as a provider crashing or timing out
+//!   (defaults to 0), and the delay in seconds before the first retry (defaults to 1), which
+//!   doubles with every subsequent retry:
+//!
+//! ```rust,ignore
+//! #[remote_fn(retries = 3, backoff = 5)]
+//! fn hello(input: String) -> String;
+//! ```
+//!
+//! Both can also be overridden at runtime with `GFAAS_RETRIES` and `GFAAS_BACKOFF` environment
+//! variables, or `retries` and `backoff` settings in `gfaas.toml`. Failures not related to the
+//! provider, such as a missing or rejected Yagna app key, aren't retried, and every retry is
+//! logged at `warn` level via the `log` crate. Note that a retried call may end up on the same
+//! provider again, as excluding the failed provider from subsequent negotiations is not
+//! supported.
+//!
+//! * resources required from providers, that is, memory and storage in GiB (default to more
+//!   than 0.5 and 1.0 respectively), and the number of CPU threads (no requirement by default):
+//!
+//! ```rust,ignore
+//! #[remote_fn(min_mem_gib = 4.0, min_storage_gib = 2.0, min_cpu_threads = 4)]
+//! fn hello(input: String) -> String;
+//! ```
+//!
+//! * any other constraints on the offers of providers, as comma-separated `key op value`
+//!   entries, where `op` is one of `==`, `!=`, `<` or `>` (`>=` and `<=` are unsupported), and
+//!   `value` may be double-quoted to keep it a string or to include commas:
+//!
+//! ```rust,ignore
+//! #[remote_fn(constraints = "golem.runtime.name == wasmtime, golem.node.id.name == \"alpha, beta\"")]
+//! fn hello(input: String) -> String;
+//! ```
+//!
+//! * directory in which the input and output data of each invocation are stored while it runs,
+//!   and the packages are cached across runs (defaults to the system's temp dir for the data, and
+//!   to the user's cache dir for the packages), and the app key and URL of the Yagna daemon
+//!   (default to `YAGNA_APPKEY` and `YAGNA_API_URL` environment variables):
+//!
+//! ```rust,ignore
+//! #[remote_fn(datadir = "/home/user/golem/datadir", app_key = "b1a8c2f4e3d5", api_url = "http://127.0.0.1:7465")]
+//! fn hello(input: String) -> String;
+//! ```
+//!
 //! Of course, nobody stops you from setting any number of parameters at once
 //!
 //! ```rust,ignore
@@ -111,7 +342,18 @@
 //!
 //! The reason that a custom wrapper around `cargo` is needed, is because the function
 //! annotated with `gfaas::remote_fn`, under-the-hood is actually automatically cross-compiled
-//! into a WASI binary.
+//! into a WASI binary. The binaries are named after your crate, the module the function is
+//! defined in (as given by the path of its source file) and the function, followed by a hash of
+//! the generated module, e.g. `mandelbrot_fractal_compute_rectangle_8f1c03d2a97e6b45.wasm` for
+//! a function in `src/fractal.rs`, and so functions of the same name in different modules of
+//! your crate don't clash.
+//!
+//! By default, the Wasm binaries are put next to your app's binary, where your app looks them up
+//! at runtime. If your app's binary is going to be moved or installed elsewhere, build it with
+//! `gfaas build --embed` (or `gfaas run --embed`) instead, which compiles the Wasm binaries first
+//! and embeds them into your app's binary. The Wasm binaries are embedded from the directory in
+//! `GFAAS_EMBED_DIR` environment variable, and so you can embed them into other builds of your
+//! crate too, e.g. into tests with `GFAAS_EMBED_DIR=$PWD/target/debug cargo test`.
 //!
 //! In addition, since the functions are cross-compiled to WASI, you need to install
 //! `wasm32-wasi` target in your used Rust toolchain. Furthermore, for that same reason, not
@@ -146,16 +388,106 @@
 //! so you can verify that everything works as expected prior to launching the tasks on the
 //! Golem Network.
 //!
+//! The `run_local` attribute only selects the default, and the same binary can be switched
+//! between backends at runtime without recompiling, either with `GFAAS_BACKEND` environment
+//! variable
+//!
+//! ```sh
+//! GFAAS_BACKEND=local gfaas run
+//! ```
+//!
+//! or with a `gfaas.toml` file in the current working directory (see [`Config`] for details)
+//!
+//! ```toml
+//! # gfaas.toml
+//! backend = "golem"
+//! ```
+//!
+//! The same settings select the compression of the packages shipped to the providers, which are
+//! stored uncompressed by default. Packages of large modules can be made smaller with Deflate,
+//! either with `GFAAS_COMPRESSION=deflate` or `compression = "deflate"` in `gfaas.toml`. Either
+//! way, packages are written reproducibly, so identical modules always make identical packages.
+//!
+//! [`Config`]: config/struct.Config.html
+//!
+//! ## Notes on testing
+//!
+//! Code calling annotated functions can be tested under plain `cargo test`, without a Golem node
+//! or the compiled Wasm modules, using the mock backend from `gfaas::testing` module. The module
+//! requires `testing` feature
+//!
+//! ```toml
+//! # Cargo.toml
+//! [dev-dependencies]
+//! gfaas = { version = "0.3", features = ["testing"] }
+//! ```
+//!
+//! With the feature enabled, annotated functions are additionally compiled natively, and the
+//! mock backend invokes them in-process. It can also inject failures, delays and timeouts
+//!
+//! ```rust,ignore
+//! use gfaas::testing::{Fault, MockBackend};
+//!
+//! let mock = MockBackend::new().with_fault(0, Fault::Timeout);
+//! gfaas::set_thread_backend(mock.clone());
+//! assert!(hello("hey there gfaas".to_string()).await.is_err());
+//! ```
+//!
+//! ## Notes on backends
+//!
+//! The expanded function doesn't talk to the Golem Network (or `ya-runtime-wasi` when run
+//! locally) directly. Instead, it hands the serialized inputs over to a [`Backend`], which is
+//! responsible for packaging, uploading the inputs, running and downloading the output.
+//! [`Golem`] and [`Local`] backends are provided, and you can plug your own by implementing
+//! the [`Backend`] trait and installing it with [`set_backend`]
+//!
+//! ```rust,ignore
+//! gfaas::set_backend(MyBackend::new());
+//! let output = hello("hey there gfaas".to_string()).await?;
+//! ```
+//!
+//! The backend installed with [`set_backend`] is used on all threads, hence it needs to be
+//! `Send` and `Sync`. A backend installed with [`set_thread_backend`] is used only on the
+//! current thread, overriding the former there. Either way, the futures returned by annotated
+//! functions aren't `Send`, so they need to be awaited on the thread they were created on.
+//!
+//! [`Backend`]: trait.Backend.html
+//! [`Golem`]: backend/struct.Golem.html
+//! [`Local`]: backend/struct.Local.html
+//! [`set_backend`]: fn.set_backend.html
+//! [`set_thread_backend`]: fn.set_thread_backend.html
+//!
 //! ## Examples
 //!
 //! A couple illustrative examples of how to use this crate can be found in the `examples/`
 //! directory. All examples require `gfaas` build tool to be built.
 
+pub mod backend;
+pub mod config;
+mod error;
+mod format;
+pub mod manifest;
+pub mod package;
+#[cfg(feature = "testing")]
+pub mod testing;
+
 pub mod __private {
     //! This is a private module. The stability of this API is not guaranteed and may change
     //! without notice in the future.
+    use crate::{
+        backend::{Completed, Invocation},
+        config::BackendKind,
+        Error, Format,
+    };
+    use futures::{
+        future,
+        stream::{self, LocalBoxStream, StreamExt},
+    };
+    use serde::{de::DeserializeOwned, Serialize};
+
     pub use anyhow;
     pub use futures;
+    pub use gfaas_macro::{shared_item, wasm_module};
     pub use serde_json;
     pub use tempfile;
     pub use tokio;
@@ -163,80 +495,54 @@ pub mod __private {
     pub use ya_runtime_wasi;
     pub use yarapi;
 
-    #[allow(unused)]
-    pub mod package {
-        //! This private module describes the structures concerning Yagna packages.
-        use anyhow::Result;
-        use std::{
-            fs,
-            io::{Cursor, Write},
-            path::Path,
+    /// Runs `invocation` of the expanded function on the current backend.
+    pub async fn run(run_local: bool, invocation: Invocation) -> Result<Vec<u8>, Error> {
+        let default = if run_local {
+            BackendKind::Local
+        } else {
+            BackendKind::Golem
         };
-        use zip::{write::FileOptions, CompressionMethod, ZipWriter};
-
-        /// Represents Yagna package which internally is represented as a zip archive.
-        pub struct Package {
-            zip_writer: ZipWriter<Cursor<Vec<u8>>>,
-            options: FileOptions,
-            module_name: Option<String>,
-        }
-
-        impl Package {
-            /// Creates new empty Yagna package.
-            pub fn new() -> Self {
-                let options = FileOptions::default().compression_method(CompressionMethod::Stored);
-                let zip_writer = ZipWriter::new(Cursor::new(Vec::new()));
-
-                Self {
-                    zip_writer,
-                    options,
-                    module_name: None,
-                }
-            }
-
-            /// Adds a Wasm modules from path.
-            pub fn add_module_from_path<P: AsRef<Path>>(&mut self, path: P) -> Result<()> {
-                let module_name = path
-                    .as_ref()
-                    .file_name()
-                    .unwrap()
-                    .to_str()
-                    .unwrap()
-                    .to_owned();
-                let contents = fs::read(path.as_ref())?;
-                self.zip_writer
-                    .start_file(&module_name, self.options.clone())?;
-                self.zip_writer.write(&contents)?;
-                self.module_name = Some(module_name);
+        crate::backend::run(default, invocation).await
+    }
 
-                Ok(())
-            }
+    /// Runs `invocations` of the expanded function on the current backend as a single batch.
+    pub async fn run_batch(
+        run_local: bool,
+        invocations: Vec<Invocation>,
+    ) -> Result<Vec<Vec<u8>>, Error> {
+        let default = if run_local {
+            BackendKind::Local
+        } else {
+            BackendKind::Golem
+        };
+        crate::backend::run_batch(default, invocations).await
+    }
 
-            /// Write the package to file at the given path.
-            pub fn write<P: AsRef<Path>>(mut self, path: P) -> Result<()> {
-                // create manifest
-                let comps: Vec<_> = self.module_name.as_ref().unwrap().split('.').collect();
-                let manifest = serde_json::json!({
-                    "id": "custom",
-                    "name": "custom",
-                    "entry-points": [{
-                        "id": comps[0],
-                        "wasm-path": self.module_name.unwrap(),
-                    }],
-                    "mount-points": [{
-                        "rw": "workdir",
-                    }]
-                });
-                self.zip_writer
-                    .start_file("manifest.json", self.options.clone())?;
-                self.zip_writer.write(&serde_json::to_vec(&manifest)?)?;
+    /// Runs `invocations` of the expanded function on the current backend as a single batch,
+    /// yielding their outputs as they complete.
+    pub fn run_streaming(
+        run_local: bool,
+        invocations: Result<Vec<Invocation>, Error>,
+    ) -> LocalBoxStream<'static, Result<Completed<Vec<u8>>, Error>> {
+        let default = if run_local {
+            BackendKind::Local
+        } else {
+            BackendKind::Golem
+        };
+        match invocations {
+            Ok(invocations) => crate::backend::run_streaming(default, invocations),
+            Err(err) => stream::once(future::ready(Err(err))).boxed_local(),
+        }
+    }
 
-                let finalized = self.zip_writer.finish()?.into_inner();
-                fs::write(path.as_ref(), finalized)?;
+    /// Serializes input argument of the expanded function.
+    pub fn serialize<T: Serialize>(format: Format, input: &T) -> Result<Vec<u8>, Error> {
+        format.serialize(input).map_err(Error::Serialize)
+    }
 
-                Ok(())
-            }
-        }
+    /// Deserializes output of the expanded function.
+    pub fn deserialize<T: DeserializeOwned>(format: Format, output: &[u8]) -> Result<T, Error> {
+        format.deserialize(output).map_err(Error::Deserialize)
     }
 }
 
@@ -246,17 +552,91 @@ pub mod __private {
 ///
 /// It is possible, via the attribute, to specify a custom Golem datadir location and budget
 ///
-/// ```rust,ignore
+/// ```rust,no_run
 /// use gfaas::remote_fn;
 ///
 /// #[remote_fn(
 ///     datadir = "/Users/kubkon/golem/datadir",
 ///     budget = 100,
 /// )]
-/// fn hello(input: String) -> String;
+/// fn hello(input: String) -> String {
+///     input.to_uppercase()
+/// }
+/// # fn main() {}
+/// ```
+///
+/// The datadir is where the input and output data of each invocation are stored while it runs,
+/// and where the packages are cached, under their SHA-256 hash, so that calls of the same
+/// function reuse them, including in later runs of the program. Similarly, you can specify the
+/// app key and the URL of the Yagna daemon to use, in place of `YAGNA_APPKEY` and
+/// `YAGNA_API_URL` environment variables
+///
+/// ```rust,no_run
+/// use gfaas::remote_fn;
+///
+/// #[remote_fn(app_key = "b1a8c2f4e3d5", api_url = "http://127.0.0.1:7465")]
+/// fn hello(input: String) -> String {
+///     input.to_uppercase()
+/// }
+/// # fn main() {}
 /// ```
 pub use gfaas_macro::remote_fn;
 
-/// Re-export of `anyhow::Error` which is the default type returned by the expanded
-/// `gfaas::remote_fn`-annotated function.
-pub use anyhow::Error;
+/// Shares the annotated item with the Wasm modules, so that functions annotated with
+/// [`remote_fn`](attr.remote_fn.html) can use it, or, in case of a type, take it as the receiver
+/// of their methods.
+///
+/// ```rust,no_run
+/// use gfaas::{remote_fn, shared};
+///
+/// #[shared]
+/// #[derive(serde::Serialize, serde::Deserialize)]
+/// struct Rect {
+///     width: f64,
+///     height: f64,
+/// }
+///
+/// #[remote_fn]
+/// impl Rect {
+///     #[remote_fn]
+///     fn area(&self) -> f64 {
+///         self.width * self.height
+///     }
+/// }
+/// # fn main() {}
+/// ```
+///
+/// The item is made public in the Wasm modules, along with the fields of the type, and any
+/// paths it refers to have to resolve there too, hence `serde::Serialize` in place of
+/// `Serialize` above. Besides types, functions, traits, constants, statics, impl blocks, use
+/// declarations and inline modules can be shared:
+///
+/// ```rust,no_run
+/// use gfaas::{remote_fn, shared};
+///
+/// #[shared]
+/// mod stats {
+///     pub fn mean(values: &[f64]) -> f64 {
+///         values.iter().sum::<f64>() / values.len() as f64
+///     }
+/// }
+///
+/// #[remote_fn]
+/// fn variance(values: Vec<f64>) -> f64 {
+///     let mean = stats::mean(&values);
+///     let squares: Vec<_> = values.iter().map(|v| (v - mean) * (v - mean)).collect();
+///     stats::mean(&squares)
+/// }
+/// # fn main() {}
+/// ```
+pub use gfaas_macro::shared;
+
+pub use backend::{
+    clear_backend, clear_thread_backend, set_backend, set_thread_backend, Backend, Completed,
+    Constraint, ConstraintOp, Invocation,
+};
+pub use config::{BackendKind, Config};
+pub use error::{Error, RemoteError};
+pub use format::Format;
+pub use manifest::{EntryPoint, Manifest, ManifestBuilder, MountPoint};
+pub use package::{CachedPackage, Compression, Package, PackageCache};
@@ -0,0 +1,318 @@
+//! Manifests of Yagna packages.
+//!
+//! The manifest describes the Wasm modules of the package, each of which is run via an entry
+//! point, and the volumes mounted into the container running them. It's serialized into
+//! `manifest.json` of the package in the format expected by `ya-runtime-wasi`.
+//!
+//! ```rust
+//! use gfaas::manifest::{EntryPoint, ManifestBuilder, MountPoint};
+//!
+//! let manifest = ManifestBuilder::new("hello", "Hello")
+//!     .with_version("0.1.0")
+//!     .with_entry_point(EntryPoint::new("hello", "hello.wasm").with_arg("--verbose"))
+//!     .with_mount_point(MountPoint::Ro("input".to_owned()))
+//!     .with_mount_point(MountPoint::Rw("workdir".to_owned()))
+//!     .build();
+//! assert_eq!(manifest.entry_points()[0].wasm_path(), "hello.wasm");
+//! ```
+//!
+//! `ya-runtime-wasi` reads the ids and the Wasm paths of the entry points and the mount points,
+//! while the version of the package and the arguments and the environment of the entry points
+//! are recorded for runtimes which support them.
+use anyhow::{anyhow, bail, Result};
+use serde_json::Value;
+use std::collections::BTreeMap;
+
+/// Manifest of a Yagna package, as built with [`ManifestBuilder`].
+///
+/// [`ManifestBuilder`]: struct.ManifestBuilder.html
+#[derive(Debug, Clone, PartialEq)]
+pub struct Manifest {
+    id: String,
+    name: String,
+    version: Option<String>,
+    entry_points: Vec<EntryPoint>,
+    mount_points: Vec<MountPoint>,
+}
+
+impl Manifest {
+    /// Id of the package.
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    /// Name of the package.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Version of the package, if any.
+    pub fn version(&self) -> Option<&str> {
+        self.version.as_deref()
+    }
+
+    /// Entry points of the package in order.
+    pub fn entry_points(&self) -> &[EntryPoint] {
+        &self.entry_points
+    }
+
+    /// Volumes mounted into the container in order.
+    pub fn mount_points(&self) -> &[MountPoint] {
+        &self.mount_points
+    }
+
+    /// Serializes the manifest into JSON, as stored in `manifest.json` of the package.
+    pub fn to_json(&self) -> Value {
+        let mut manifest = serde_json::json!({
+            "id": self.id,
+            "name": self.name,
+            "entry-points": self.entry_points.iter().map(EntryPoint::to_json).collect::<Vec<_>>(),
+            "mount-points": self.mount_points.iter().map(MountPoint::to_json).collect::<Vec<_>>(),
+        });
+        if let Some(version) = &self.version {
+            manifest["version"] = version.clone().into();
+        }
+        manifest
+    }
+
+    /// Parses the manifest from JSON, as stored in `manifest.json` of the package.
+    pub fn from_json(json: &Value) -> Result<Self> {
+        let version = match json.get("version") {
+            Some(version) => Some(
+                version
+                    .as_str()
+                    .ok_or_else(|| anyhow!("'version' is not a string"))?
+                    .to_owned(),
+            ),
+            None => None,
+        };
+        Ok(Self {
+            id: str_field(json, "id")?,
+            name: str_field(json, "name")?,
+            version,
+            entry_points: array_field(json, "entry-points")?
+                .iter()
+                .map(EntryPoint::from_json)
+                .collect::<Result<_>>()?,
+            mount_points: array_field(json, "mount-points")?
+                .iter()
+                .map(MountPoint::from_json)
+                .collect::<Result<_>>()?,
+        })
+    }
+}
+
+/// Builder of a [`Manifest`].
+///
+/// [`Manifest`]: struct.Manifest.html
+#[derive(Debug, Clone)]
+pub struct ManifestBuilder {
+    manifest: Manifest,
+}
+
+impl ManifestBuilder {
+    /// Creates new builder of the manifest of package `id` called `name`, with no entry points
+    /// or mount points.
+    pub fn new<S: Into<String>, T: Into<String>>(id: S, name: T) -> Self {
+        Self {
+            manifest: Manifest {
+                id: id.into(),
+                name: name.into(),
+                version: None,
+                entry_points: Vec::new(),
+                mount_points: Vec::new(),
+            },
+        }
+    }
+
+    /// Sets the version of the package.
+    pub fn with_version<S: Into<String>>(mut self, version: S) -> Self {
+        self.manifest.version = Some(version.into());
+        self
+    }
+
+    /// Appends an entry point.
+    pub fn with_entry_point(mut self, entry_point: EntryPoint) -> Self {
+        self.manifest.entry_points.push(entry_point);
+        self
+    }
+
+    /// Appends a mount point.
+    pub fn with_mount_point(mut self, mount_point: MountPoint) -> Self {
+        self.manifest.mount_points.push(mount_point);
+        self
+    }
+
+    /// Builds the manifest.
+    pub fn build(self) -> Manifest {
+        self.manifest
+    }
+}
+
+/// Entry point of a Yagna package, which runs one of the Wasm modules of the package.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EntryPoint {
+    id: String,
+    wasm_path: String,
+    args: Vec<String>,
+    env: BTreeMap<String, String>,
+}
+
+impl EntryPoint {
+    /// Creates new entry point `id` running the module at `wasm_path` within the package.
+    pub fn new<S: Into<String>, T: Into<String>>(id: S, wasm_path: T) -> Self {
+        Self {
+            id: id.into(),
+            wasm_path: wasm_path.into(),
+            args: Vec::new(),
+            env: BTreeMap::new(),
+        }
+    }
+
+    /// Appends an argument the module is run with.
+    pub fn with_arg<S: Into<String>>(mut self, arg: S) -> Self {
+        self.args.push(arg.into());
+        self
+    }
+
+    /// Sets environment variable `key` to `value` for the module.
+    pub fn with_env<K: Into<String>, V: Into<String>>(mut self, key: K, value: V) -> Self {
+        self.env.insert(key.into(), value.into());
+        self
+    }
+
+    /// Id of the entry point.
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    /// Path to the Wasm module within the package.
+    pub fn wasm_path(&self) -> &str {
+        &self.wasm_path
+    }
+
+    /// Arguments the module is run with in order.
+    pub fn args(&self) -> &[String] {
+        &self.args
+    }
+
+    /// Environment variables of the module.
+    pub fn env(&self) -> &BTreeMap<String, String> {
+        &self.env
+    }
+
+    fn to_json(&self) -> Value {
+        let mut entry_point = serde_json::json!({
+            "id": self.id,
+            "wasm-path": self.wasm_path,
+        });
+        if !self.args.is_empty() {
+            entry_point["args"] = self.args.clone().into();
+        }
+        if !self.env.is_empty() {
+            entry_point["env"] = self
+                .env
+                .iter()
+                .map(|(key, value)| (key.clone(), value.clone().into()))
+                .collect::<serde_json::Map<_, _>>()
+                .into();
+        }
+        entry_point
+    }
+
+    fn from_json(json: &Value) -> Result<Self> {
+        let args = array_field(json, "args")?
+            .iter()
+            .map(|arg| {
+                arg.as_str()
+                    .map(str::to_owned)
+                    .ok_or_else(|| anyhow!("'args' is not an array of strings"))
+            })
+            .collect::<Result<_>>()?;
+        let env = match json.get("env") {
+            Some(env) => env
+                .as_object()
+                .ok_or_else(|| anyhow!("'env' is not an object"))?
+                .iter()
+                .map(|(key, value)| {
+                    value
+                        .as_str()
+                        .map(|value| (key.clone(), value.to_owned()))
+                        .ok_or_else(|| anyhow!("'env' is not an object of strings"))
+                })
+                .collect::<Result<_>>()?,
+            None => BTreeMap::new(),
+        };
+        Ok(Self {
+            id: str_field(json, "id")?,
+            wasm_path: str_field(json, "wasm-path")?,
+            args,
+            env,
+        })
+    }
+}
+
+/// Volume mounted into the container at the given path.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MountPoint {
+    /// Read-only volume.
+    Ro(String),
+    /// Read-write volume.
+    Rw(String),
+    /// Write-only volume.
+    Wo(String),
+}
+
+impl MountPoint {
+    /// Path the volume is mounted at.
+    pub fn path(&self) -> &str {
+        match self {
+            Self::Ro(path) | Self::Rw(path) | Self::Wo(path) => path,
+        }
+    }
+
+    fn to_json(&self) -> Value {
+        let access = match self {
+            Self::Ro(_) => "ro",
+            Self::Rw(_) => "rw",
+            Self::Wo(_) => "wo",
+        };
+        serde_json::json!({ access: self.path() })
+    }
+
+    fn from_json(json: &Value) -> Result<Self> {
+        let (access, path) = match json.as_object() {
+            Some(object) if object.len() == 1 => object.iter().next().unwrap(),
+            _ => bail!("mount point is not an object with a single key"),
+        };
+        let path = path
+            .as_str()
+            .ok_or_else(|| anyhow!("'{}' is not a string", access))?
+            .to_owned();
+        match access.as_str() {
+            "ro" => Ok(Self::Ro(path)),
+            "rw" => Ok(Self::Rw(path)),
+            "wo" => Ok(Self::Wo(path)),
+            x => bail!("unknown mount point '{}': expected 'ro', 'rw' or 'wo'", x),
+        }
+    }
+}
+
+/// Extracts string field `key` of JSON object.
+fn str_field(json: &Value, key: &str) -> Result<String> {
+    json.get(key)
+        .and_then(Value::as_str)
+        .map(str::to_owned)
+        .ok_or_else(|| anyhow!("'{}' is missing or is not a string", key))
+}
+
+/// Extracts array field `key` of JSON object, which is empty if missing.
+fn array_field<'a>(json: &'a Value, key: &str) -> Result<&'a [Value]> {
+    match json.get(key) {
+        Some(value) => value
+            .as_array()
+            .map(Vec::as_slice)
+            .ok_or_else(|| anyhow!("'{}' is not an array", key)),
+        None => Ok(&[]),
+    }
+}
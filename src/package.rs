@@ -0,0 +1,436 @@
+//! Yagna packages, which are zip archives holding the Wasm modules and the [`Manifest`]
+//! describing them.
+//!
+//! Besides writing the packages shipped to the providers, existing packages can be opened and
+//! inspected, which comes in handy when debugging what actually got shipped:
+//!
+//! ```rust,no_run
+//! use gfaas::Package;
+//!
+//! let package = Package::open("package.zip").unwrap();
+//! for entry_point in package.manifest().entry_points() {
+//!     let module = package.module(entry_point.wasm_path()).unwrap();
+//!     println!("{}: {} bytes", entry_point.id(), module.len());
+//! }
+//! ```
+//!
+//! Packages are reproducible: the files are written in order of their names and with fixed
+//! timestamps and permissions, so that identical packages are identical byte for byte. They are
+//! cached by [`PackageCache`] under the SHA-256 hash of their modules and manifest, so that
+//! identical packages are written once and can be referred to by the hash.
+//!
+//! [`Manifest`]: ../manifest/struct.Manifest.html
+//! [`PackageCache`]: struct.PackageCache.html
+use crate::manifest::{EntryPoint, Manifest, ManifestBuilder, MountPoint};
+use anyhow::{anyhow, bail, Context, Result};
+use lazy_static::lazy_static;
+use sha2::{Digest, Sha256};
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    fmt, fs,
+    io::{Cursor, Read, Write},
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::{Mutex, PoisonError},
+    time::SystemTime,
+};
+use tempfile::NamedTempFile;
+use zip::{write::FileOptions, CompressionMethod, DateTime, ZipArchive, ZipWriter};
+
+/// Name of the manifest within the package.
+const MANIFEST_NAME: &str = "manifest.json";
+
+lazy_static! {
+    /// Packages in [`PackageCache`]s verified by the current process, along with their size and
+    /// modification time, so that they're only verified again once they change.
+    ///
+    /// [`PackageCache`]: struct.PackageCache.html
+    static ref VERIFIED: Mutex<HashMap<PathBuf, (u64, SystemTime)>> = Mutex::new(HashMap::new());
+}
+
+/// Id of the entry point of module `module_name`, which is its name up to the extension.
+fn entry_point_id(module_name: &str) -> &str {
+    module_name.split('.').next().unwrap()
+}
+
+/// Compression of the files within a Yagna package.
+///
+/// Only the methods `ya-runtime-wasi` can read the packages with are supported. In particular,
+/// Zstandard isn't offered since `zip` 0.5, which `ya-runtime-wasi` unpacks the packages with,
+/// can neither read nor write it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    /// Files are stored uncompressed, which is the default.
+    Stored,
+    /// Files are compressed with Deflate, which makes the packages of large modules smaller at
+    /// the cost of the time spent compressing them.
+    Deflate,
+}
+
+impl Default for Compression {
+    fn default() -> Self {
+        Self::Stored
+    }
+}
+
+impl FromStr for Compression {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "stored" => Ok(Self::Stored),
+            "deflate" => Ok(Self::Deflate),
+            x => bail!(
+                "unknown compression '{}': expected 'stored' or 'deflate'",
+                x
+            ),
+        }
+    }
+}
+
+impl fmt::Display for Compression {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Stored => write!(f, "stored"),
+            Self::Deflate => write!(f, "deflate"),
+        }
+    }
+}
+
+/// Represents Yagna package which internally is represented as a zip archive.
+///
+/// The package may hold any number of Wasm modules. Unless written with a custom manifest, each
+/// of them is an entry point of the package named after the module.
+#[derive(Default)]
+pub struct Package {
+    modules: BTreeMap<String, Vec<u8>>,
+    manifest: Option<Manifest>,
+    compression: Compression,
+}
+
+impl Package {
+    /// Creates new empty Yagna package.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Opens the Yagna package at the given path.
+    ///
+    /// The package is validated on the way: its manifest has to be well-formed, and each of its
+    /// entry points has to refer to a valid Wasm module within the package. The package is
+    /// considered compressed with Deflate if any of its files is.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let contents = fs::read(path.as_ref())
+            .with_context(|| format!("reading package '{}'", path.as_ref().display()))?;
+        let mut archive = ZipArchive::new(Cursor::new(contents))?;
+
+        let mut manifest = None;
+        let mut modules = BTreeMap::new();
+        let mut compression = Compression::Stored;
+        for i in 0..archive.len() {
+            let mut file = archive.by_index(i)?;
+            if file.is_dir() {
+                continue;
+            }
+            if file.compression() == CompressionMethod::Deflated {
+                compression = Compression::Deflate;
+            }
+            let mut contents = Vec::new();
+            file.read_to_end(&mut contents)?;
+            if file.name() == MANIFEST_NAME {
+                let json = serde_json::from_slice(&contents).context("parsing manifest")?;
+                manifest = Some(Manifest::from_json(&json).context("parsing manifest")?);
+            } else {
+                modules.insert(file.name().to_owned(), contents);
+            }
+        }
+        let manifest = manifest.ok_or_else(|| anyhow!("package contains no manifest"))?;
+
+        let package = Self {
+            modules,
+            manifest: Some(manifest),
+            compression,
+        };
+        let manifest = package.manifest();
+        package.validate(&manifest)?;
+        for entry_point in manifest.entry_points() {
+            let module = package.module(entry_point.wasm_path()).unwrap();
+            wasmparser::validate(module, None).with_context(|| {
+                format!(
+                    "entry point '{}' refers to invalid Wasm module '{}'",
+                    entry_point.id(),
+                    entry_point.wasm_path()
+                )
+            })?;
+        }
+
+        Ok(package)
+    }
+
+    /// Adds a Wasm modules from path.
+    pub fn add_module_from_path<P: AsRef<Path>>(&mut self, path: P) -> Result<()> {
+        let module_name = path
+            .as_ref()
+            .file_name()
+            .unwrap()
+            .to_str()
+            .unwrap()
+            .to_owned();
+        let contents = fs::read(path.as_ref())?;
+        self.add_module_from_bytes(module_name, &contents)
+    }
+
+    /// Adds a Wasm module called `module_name` (including `.wasm` extension) with the
+    /// given contents, such as embedded in the binary with `include_bytes!`.
+    pub fn add_module_from_bytes<S: Into<String>>(
+        &mut self,
+        module_name: S,
+        contents: &[u8],
+    ) -> Result<()> {
+        let module_name = module_name.into();
+        // Modules are run by the ids of their entry points, which have to be unique.
+        if self
+            .module_names()
+            .any(|other| entry_point_id(other) == entry_point_id(&module_name))
+        {
+            bail!("duplicate Wasm module '{}'", module_name);
+        }
+        self.modules.insert(module_name, contents.to_vec());
+
+        Ok(())
+    }
+
+    /// Sets the compression of the files within the package.
+    pub fn set_compression(&mut self, compression: Compression) {
+        self.compression = compression;
+    }
+
+    /// Compression of the files within the package.
+    pub fn compression(&self) -> Compression {
+        self.compression
+    }
+
+    /// Names of the modules, or generally the files other than the manifest, in the package in
+    /// order.
+    pub fn module_names(&self) -> impl Iterator<Item = &str> {
+        self.modules.keys().map(String::as_str)
+    }
+
+    /// Contents of the module called `module_name`, if any.
+    pub fn module(&self, module_name: &str) -> Option<&[u8]> {
+        self.modules.get(module_name).map(Vec::as_slice)
+    }
+
+    /// Manifest of the package, which, unless the package was opened, holds an entry point per
+    /// module and mounts `workdir` read-write.
+    pub fn manifest(&self) -> Manifest {
+        if let Some(manifest) = &self.manifest {
+            return manifest.clone();
+        }
+        self.module_names()
+            .fold(
+                ManifestBuilder::new("custom", "custom"),
+                |builder, module_name| {
+                    builder
+                        .with_entry_point(EntryPoint::new(entry_point_id(module_name), module_name))
+                },
+            )
+            .with_mount_point(MountPoint::Rw("workdir".to_owned()))
+            .build()
+    }
+
+    /// SHA-256 hash of the modules, the manifest and the compression of the package as a hex
+    /// string, which identifies the written zip archive.
+    pub fn hash(&self) -> String {
+        let mut hasher = Sha256::new();
+        // Lengths delimit the names and the contents, so that they can't run into each other.
+        for (module_name, contents) in &self.modules {
+            for bytes in &[module_name.as_bytes(), contents] {
+                hasher.input((bytes.len() as u64).to_le_bytes());
+                hasher.input(bytes);
+            }
+        }
+        hasher.input(self.manifest().to_json().to_string());
+        hasher.input(self.compression.to_string());
+        format!("{:x}", hasher.result())
+    }
+
+    /// Write the package to file at the given path, with its manifest.
+    pub fn write<P: AsRef<Path>>(self, path: P) -> Result<()> {
+        let manifest = self.manifest();
+        self.write_with_manifest(&manifest, path)
+    }
+
+    /// Write the package to file at the given path, with the given manifest, whose entry
+    /// points have to refer to the modules of the package.
+    pub fn write_with_manifest<P: AsRef<Path>>(self, manifest: &Manifest, path: P) -> Result<()> {
+        if self.modules.is_empty() {
+            bail!("package contains no Wasm modules");
+        }
+        self.validate(manifest)?;
+
+        let compression_method = match self.compression {
+            Compression::Stored => CompressionMethod::Stored,
+            Compression::Deflate => CompressionMethod::Deflated,
+        };
+        // Fixed timestamps and permissions make the archive depend on the contents only.
+        let options = FileOptions::default()
+            .compression_method(compression_method)
+            .last_modified_time(DateTime::default())
+            .unix_permissions(0o644);
+        let mut zip_writer = ZipWriter::new(Cursor::new(Vec::new()));
+        for (module_name, contents) in &self.modules {
+            zip_writer.start_file(module_name, options)?;
+            zip_writer.write_all(contents)?;
+        }
+        zip_writer.start_file(MANIFEST_NAME, options)?;
+        zip_writer.write_all(&serde_json::to_vec(&manifest.to_json())?)?;
+
+        let finalized = zip_writer.finish()?.into_inner();
+        fs::write(path.as_ref(), finalized)?;
+
+        Ok(())
+    }
+
+    /// Checks that the entry points of `manifest` are unique and refer to the modules of the
+    /// package.
+    fn validate(&self, manifest: &Manifest) -> Result<()> {
+        let mut ids = HashSet::new();
+        for entry_point in manifest.entry_points() {
+            if !ids.insert(entry_point.id()) {
+                bail!("duplicate entry point '{}'", entry_point.id());
+            }
+            if self.module(entry_point.wasm_path()).is_none() {
+                bail!(
+                    "entry point '{}' refers to Wasm module '{}' missing from the package",
+                    entry_point.id(),
+                    entry_point.wasm_path()
+                );
+            }
+        }
+        Ok(())
+    }
+}
+
+/// On-disk cache of Yagna packages, keyed by their [`hash`].
+///
+/// Packages are stored in the cache directory as `<hash>.zip`, and are never evicted; remove
+/// the directory to reclaim the space. The cache may be shared by any number of processes.
+///
+/// Cached packages aren't trusted by their name alone: before they're first reused by a process,
+/// and whenever they change, they're opened and hashed anew, and replaced if they don't match
+/// the hash.
+///
+/// ```rust,no_run
+/// use gfaas::{Package, PackageCache};
+///
+/// let mut package = Package::new();
+/// package.add_module_from_path("hello.wasm").unwrap();
+///
+/// let cache = PackageCache::new("packages");
+/// let cached = cache.insert(package).unwrap();
+/// assert_eq!(cache.get(cached.hash()).unwrap(), cached.path());
+/// ```
+///
+/// [`hash`]: struct.Package.html#method.hash
+#[derive(Debug, Clone)]
+pub struct PackageCache {
+    dir: PathBuf,
+}
+
+impl PackageCache {
+    /// Creates new cache of packages within `dir`, which is created once needed.
+    pub fn new<P: Into<PathBuf>>(dir: P) -> Self {
+        Self { dir: dir.into() }
+    }
+
+    /// Directory of the cache.
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Path to the package with the given hash, if cached and valid.
+    ///
+    /// The package is opened and hashed anew only the first time it's looked up by the current
+    /// process, or if it has changed since.
+    pub fn get(&self, hash: &str) -> Option<PathBuf> {
+        let path = self.path(hash);
+        let stamp = stamp(&path)?;
+        let verified = VERIFIED
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .get(&path)
+            == Some(&stamp);
+        if verified {
+            return Some(path);
+        }
+        match Package::open(&path) {
+            Ok(package) if package.hash() == hash => {
+                verify(&path, stamp);
+                Some(path)
+            }
+            _ => None,
+        }
+    }
+
+    /// Writes `package` to the cache, unless a valid package with the same hash is already
+    /// cached. An invalid one is replaced.
+    pub fn insert(&self, package: Package) -> Result<CachedPackage> {
+        let hash = package.hash();
+        let path = self.path(&hash);
+        if self.get(&hash).is_none() {
+            fs::create_dir_all(&self.dir)
+                .with_context(|| format!("creating cache dir '{}'", self.dir.display()))?;
+            // Written aside and moved into place, so that other processes never see a partially
+            // written package.
+            let temp_path = NamedTempFile::new_in(&self.dir)?.into_temp_path();
+            package.write(&temp_path)?;
+            temp_path.persist(&path)?;
+            if let Some(stamp) = stamp(&path) {
+                verify(&path, stamp);
+            }
+        }
+        Ok(CachedPackage { hash, path })
+    }
+
+    fn path(&self, hash: &str) -> PathBuf {
+        self.dir.join(format!("{}.zip", hash))
+    }
+}
+
+/// Records the package at `path` as verified while it has the given `stamp`.
+fn verify(path: &Path, stamp: (u64, SystemTime)) {
+    VERIFIED
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .insert(path.to_owned(), stamp);
+}
+
+/// Size and modification time of the file at `path`, if it's a file.
+fn stamp(path: &Path) -> Option<(u64, SystemTime)> {
+    let metadata = fs::metadata(path)
+        .ok()
+        .filter(|metadata| metadata.is_file())?;
+    Some((metadata.len(), metadata.modified().ok()?))
+}
+
+/// Package stored in [`PackageCache`].
+///
+/// [`PackageCache`]: struct.PackageCache.html
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CachedPackage {
+    hash: String,
+    path: PathBuf,
+}
+
+impl CachedPackage {
+    /// Hash of the package.
+    pub fn hash(&self) -> &str {
+        &self.hash
+    }
+
+    /// Path to the package within the cache.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
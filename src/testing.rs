@@ -0,0 +1,215 @@
+//! Utilities for testing code calling `gfaas::remote_fn`-annotated functions.
+//!
+//! This module is only available with the `testing` feature enabled, typically in your
+//! `[dev-dependencies]`
+//!
+//! ```toml
+//! # Cargo.toml
+//! [dev-dependencies]
+//! gfaas = { version = "0.3", features = ["testing"] }
+//! ```
+//!
+//! With the feature enabled, `gfaas::remote_fn` additionally compiles the body of the annotated
+//! function natively, so that [`MockBackend`] can invoke it directly in-process, without a
+//! Golem node or the compiled Wasm module. Note that this means any crates the function depends
+//! on (those you've listed in `[gfaas_dependencies]`) need to be available natively too, for
+//! instance as `[dev-dependencies]`.
+//!
+//! ```rust,ignore
+//! use gfaas::testing::{Fault, MockBackend};
+//!
+//! #[actix_rt::test]
+//! async fn failed_chunk_aborts_sum() {
+//!     let mock = MockBackend::new().with_fault(1, Fault::Error("provider crashed".into()));
+//!     gfaas::set_thread_backend(mock.clone());
+//!
+//!     assert!(sum_in_chunks((0..100).collect()).await.is_err());
+//!     assert_eq!(mock.calls(), 2);
+//! }
+//! ```
+//!
+//! Alternatively, the mock backend can be selected at runtime with `GFAAS_BACKEND=mock`.
+//!
+//! To check what the expanded functions hand over to the backend, wrap the mock in
+//! [`RecordingBackend`], which records every call.
+//!
+//! [`MockBackend`]: struct.MockBackend.html
+//! [`RecordingBackend`]: struct.RecordingBackend.html
+use crate::{
+    backend::{Backend, Constraint, Invocation},
+    Error,
+};
+use anyhow::anyhow;
+use futures::future::{FutureExt, LocalBoxFuture};
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::time;
+
+/// Fault injected into a call to [`MockBackend`].
+///
+/// [`MockBackend`]: struct.MockBackend.html
+#[derive(Debug, Clone)]
+pub enum Fault {
+    /// Fail the call with `gfaas::Error::Run` with the given error message.
+    Error(String),
+    /// Fail the call with `gfaas::Error::Backend` with the given error message. Unlike the
+    /// other faults, it's not transient, so the call isn't retried.
+    Backend(String),
+    /// Delay the call. If the delay exceeds the timeout of the invocation, the call times out.
+    Delay(Duration),
+    /// Time out the call immediately with `gfaas::Error::Timeout`.
+    Timeout,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    calls: usize,
+    delay: Option<Duration>,
+    faults: HashMap<usize, Fault>,
+}
+
+/// Backend invoking the natively compiled body of the function in-process.
+///
+/// The inputs and the output go through the same serialization round-trip as they would on
+/// any other backend. `MockBackend` is cheaply cloneable, and all clones share the same state,
+/// so you can keep a handle to the backend after installing it with [`set_thread_backend`], or
+/// with [`set_backend`] for all threads.
+///
+/// [`set_thread_backend`]: ../fn.set_thread_backend.html
+/// [`set_backend`]: ../fn.set_backend.html
+#[derive(Debug, Default, Clone)]
+pub struct MockBackend {
+    state: Arc<Mutex<State>>,
+}
+
+impl MockBackend {
+    /// Creates new mock backend which runs every call successfully.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Delays every call by `delay`.
+    pub fn with_delay(self, delay: Duration) -> Self {
+        self.state.lock().unwrap().delay = Some(delay);
+        self
+    }
+
+    /// Injects `fault` into the `call`-th call (counting from 0).
+    pub fn with_fault(self, call: usize, fault: Fault) -> Self {
+        self.state.lock().unwrap().faults.insert(call, fault);
+        self
+    }
+
+    /// Number of calls made so far.
+    pub fn calls(&self) -> usize {
+        self.state.lock().unwrap().calls
+    }
+}
+
+impl Backend for MockBackend {
+    fn run<'a>(&'a self, invocation: Invocation) -> LocalBoxFuture<'a, Result<Vec<u8>, Error>> {
+        async move {
+            let (delay, fault) = {
+                let mut state = self.state.lock().unwrap();
+                let call = state.calls;
+                state.calls += 1;
+                (state.delay, state.faults.remove(&call))
+            };
+            let delay = match fault {
+                Some(Fault::Error(msg)) => return Err(Error::Run(anyhow!(msg))),
+                Some(Fault::Backend(msg)) => return Err(Error::Backend(anyhow!(msg))),
+                Some(Fault::Timeout) => return Err(Error::Timeout(invocation.timeout())),
+                Some(Fault::Delay(delay)) => Some(delay),
+                None => delay,
+            };
+            if let Some(delay) = delay {
+                if delay >= invocation.timeout() {
+                    time::delay_for(invocation.timeout()).await;
+                    return Err(Error::Timeout(invocation.timeout()));
+                }
+                time::delay_for(delay).await;
+            }
+
+            let native = invocation.native().ok_or_else(|| {
+                Error::Backend(anyhow!(
+                    "function '{}' wasn't compiled natively: is 'testing' feature of gfaas enabled?",
+                    invocation.module_name()
+                ))
+            })?;
+            native(invocation.inputs().to_vec()).map_err(Error::Run)
+        }
+        .boxed_local()
+    }
+}
+
+/// Call recorded by [`RecordingBackend`].
+///
+/// [`RecordingBackend`]: struct.RecordingBackend.html
+#[derive(Debug, Clone)]
+pub struct Call {
+    /// Name of the invoked module.
+    pub module_name: String,
+    /// Serialized inputs of the invocation.
+    pub inputs: Vec<Vec<u8>>,
+    /// Constraints of the invocation.
+    pub constraints: Vec<Constraint>,
+    /// Serialized output of the call, or `None` if the call failed.
+    pub output: Option<Vec<u8>>,
+}
+
+/// Backend running invocations with a [`MockBackend`], recording every call.
+///
+/// Like `MockBackend`, it's cheaply cloneable, and all clones share the recorded calls.
+///
+/// ```rust,ignore
+/// use gfaas::testing::RecordingBackend;
+///
+/// let backend = RecordingBackend::default();
+/// gfaas::set_thread_backend(backend.clone());
+///
+/// hello("hey there gfaas".to_string()).await?;
+/// assert_eq!(backend.calls()[0].inputs, [b"\"hey there gfaas\"".to_vec()]);
+/// ```
+///
+/// [`MockBackend`]: struct.MockBackend.html
+#[derive(Debug, Default, Clone)]
+pub struct RecordingBackend {
+    mock: MockBackend,
+    calls: Arc<Mutex<Vec<Call>>>,
+}
+
+impl RecordingBackend {
+    /// Creates new recording backend running invocations with `mock`.
+    pub fn new(mock: MockBackend) -> Self {
+        Self {
+            mock,
+            calls: Arc::default(),
+        }
+    }
+
+    /// Calls made so far, in the order they finished.
+    pub fn calls(&self) -> Vec<Call> {
+        self.calls.lock().unwrap().clone()
+    }
+}
+
+impl Backend for RecordingBackend {
+    fn run<'a>(&'a self, invocation: Invocation) -> LocalBoxFuture<'a, Result<Vec<u8>, Error>> {
+        async move {
+            let mut call = Call {
+                module_name: invocation.module_name().to_owned(),
+                inputs: invocation.inputs().to_vec(),
+                constraints: invocation.constraints().to_vec(),
+                output: None,
+            };
+            let output = self.mock.run(invocation).await;
+            call.output = output.as_ref().ok().cloned();
+            self.calls.lock().unwrap().push(call);
+            output
+        }
+        .boxed_local()
+    }
+}
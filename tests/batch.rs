@@ -0,0 +1,71 @@
+use gfaas::{
+    remote_fn,
+    testing::{Fault, MockBackend},
+    Error, RemoteError,
+};
+use std::time::Duration;
+
+#[remote_fn]
+fn square(x: u64) -> u64 {
+    x * x
+}
+
+#[remote_fn]
+fn add(a: u64, b: u64) -> u64 {
+    a + b
+}
+
+#[remote_fn]
+fn parse(input: &str) -> Result<u64, String> {
+    input
+        .parse()
+        .map_err(|_| format!("'{}' is not a number", input))
+}
+
+#[actix_rt::test]
+async fn ordering() {
+    // Earlier invocations complete later.
+    let mock = MockBackend::new()
+        .with_fault(0, Fault::Delay(Duration::from_millis(100)))
+        .with_fault(1, Fault::Delay(Duration::from_millis(50)));
+    gfaas::set_thread_backend(mock.clone());
+
+    assert_eq!(square::batch(vec![1, 2, 3]).await.unwrap(), [1, 4, 9]);
+    assert_eq!(add::batch(vec![(1, 2), (3, 4)]).await.unwrap(), [3, 7]);
+    assert_eq!(mock.calls(), 5);
+}
+
+#[actix_rt::test]
+async fn empty() {
+    let mock = MockBackend::new();
+    gfaas::set_thread_backend(mock.clone());
+
+    assert!(square::batch(vec![]).await.unwrap().is_empty());
+    assert_eq!(mock.calls(), 0);
+}
+
+#[actix_rt::test]
+async fn failure() {
+    let mock = MockBackend::new().with_fault(1, Fault::Error("provider crashed".to_owned()));
+    gfaas::set_thread_backend(mock.clone());
+
+    match square::batch(vec![1, 2, 3]).await {
+        Err(Error::Run(err)) => assert_eq!(err.to_string(), "provider crashed"),
+        res => panic!("unexpected result: {:?}", res),
+    }
+}
+
+#[actix_rt::test]
+async fn application_errors() {
+    gfaas::set_thread_backend(MockBackend::new());
+
+    let outputs = parse::batch(vec!["1".to_owned(), "x".to_owned(), "3".to_owned()])
+        .await
+        .unwrap();
+    match &outputs[..] {
+        [Ok(1), Err(RemoteError::Application(err)), Ok(3)] => {
+            assert_eq!(err, "'x' is not a number")
+        }
+        outputs => panic!("unexpected outputs: {:?}", outputs),
+    }
+}
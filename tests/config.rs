@@ -0,0 +1,82 @@
+use gfaas::{BackendKind, Compression, Config};
+use std::{env, fs, time::Duration};
+
+const VARS: &[&str] = &[
+    "GFAAS_CONFIG",
+    "GFAAS_BACKEND",
+    "GFAAS_RETRIES",
+    "GFAAS_BACKOFF",
+    "GFAAS_COMPRESSION",
+];
+
+#[test]
+fn from_file() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("gfaas.toml");
+
+    fs::write(&path, "").unwrap();
+    let config = Config::from_file(&path).unwrap();
+    assert_eq!(config.backend(), None);
+    assert_eq!(config.retries(), None);
+
+    fs::write(&path, "retries = -1").unwrap();
+    assert!(Config::from_file(&path).is_err());
+    fs::write(&path, "backend = \"cloud\"").unwrap();
+    assert!(Config::from_file(&path).is_err());
+    assert!(Config::from_file(dir.path().join("missing.toml")).is_err());
+}
+
+// The environment is shared by the whole process, so it's only modified by this one test.
+#[test]
+fn load() {
+    for var in VARS {
+        env::remove_var(var);
+    }
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("gfaas.toml");
+    fs::write(
+        &path,
+        "backend = \"local\"\nretries = 2\nbackoff = 5\ncompression = \"deflate\"\n",
+    )
+    .unwrap();
+    env::set_var("GFAAS_CONFIG", &path);
+
+    // The file is read from `GFAAS_CONFIG`.
+    let config = Config::load().unwrap();
+    assert_eq!(config.backend(), Some(BackendKind::Local));
+    assert_eq!(config.retries(), Some(2));
+    assert_eq!(config.backoff(), Some(Duration::from_secs(5)));
+    assert_eq!(config.compression(), Some(Compression::Deflate));
+
+    // The environment takes precedence over the file, setting by setting.
+    env::set_var("GFAAS_BACKEND", "golem");
+    env::set_var("GFAAS_RETRIES", "0");
+    let config = Config::load().unwrap();
+    assert_eq!(config.backend(), Some(BackendKind::Golem));
+    assert_eq!(config.retries(), Some(0));
+    assert_eq!(config.backoff(), Some(Duration::from_secs(5)));
+    assert_eq!(config.compression(), Some(Compression::Deflate));
+
+    env::set_var("GFAAS_BACKOFF", "1");
+    env::set_var("GFAAS_COMPRESSION", "stored");
+    let config = Config::load().unwrap();
+    assert_eq!(config.backoff(), Some(Duration::from_secs(1)));
+    assert_eq!(config.compression(), Some(Compression::Stored));
+
+    // Malformed variables are errors rather than ignored.
+    env::set_var("GFAAS_RETRIES", "many");
+    assert!(Config::load().is_err());
+    env::remove_var("GFAAS_RETRIES");
+
+    // A missing file is an error only if given by `GFAAS_CONFIG`.
+    fs::remove_file(&path).unwrap();
+    assert!(Config::load().is_err());
+    env::remove_var("GFAAS_CONFIG");
+    let config = Config::load().unwrap();
+    assert_eq!(config.backend(), Some(BackendKind::Golem));
+    assert_eq!(config.retries(), None);
+
+    for var in VARS {
+        env::remove_var(var);
+    }
+}
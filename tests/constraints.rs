@@ -0,0 +1,38 @@
+use gfaas::{remote_fn, testing::RecordingBackend, Constraint, ConstraintOp};
+use serde_json::json;
+
+#[remote_fn(
+    constraints = "golem.node.id.name == \"alpha, beta\", golem.inf.mem.gib > 2, golem.srv.caps.multi-activity != true"
+)]
+fn hello(input: String) -> String {
+    input
+}
+
+#[actix_rt::test]
+async fn quoted_commas() {
+    let backend = RecordingBackend::default();
+    gfaas::set_thread_backend(backend.clone());
+
+    assert_eq!(hello("hey".to_owned()).await.unwrap(), "hey");
+    let constraint = |key: &str, op, value| Constraint {
+        key: key.to_owned(),
+        op,
+        value,
+    };
+    assert_eq!(
+        backend.calls()[0].constraints,
+        [
+            constraint(
+                "golem.node.id.name",
+                ConstraintOp::Equal,
+                json!("alpha, beta")
+            ),
+            constraint("golem.inf.mem.gib", ConstraintOp::GreaterThan, json!(2)),
+            constraint(
+                "golem.srv.caps.multi-activity",
+                ConstraintOp::NotEqual,
+                json!(true)
+            ),
+        ]
+    );
+}
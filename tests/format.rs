@@ -0,0 +1,93 @@
+use gfaas::{remote_fn, testing::RecordingBackend, Format};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct Point {
+    x: i64,
+    y: f64,
+    label: Option<String>,
+    tags: Vec<u8>,
+}
+
+fn point() -> Point {
+    Point {
+        x: -3,
+        y: 0.5,
+        label: Some("origin".to_owned()),
+        tags: vec![0, 255],
+    }
+}
+
+fn moved(point: Point, (dx, dy): (i64, f64)) -> Point {
+    Point {
+        x: point.x + dx,
+        y: point.y + dy,
+        ..point
+    }
+}
+
+#[remote_fn]
+fn move_json(point: Point, by: (i64, f64)) -> Point {
+    moved(point, by)
+}
+
+#[remote_fn(format = "bincode")]
+fn move_bincode(point: Point, by: (i64, f64)) -> Point {
+    moved(point, by)
+}
+
+#[remote_fn(format = "cbor")]
+fn move_cbor(point: Point, by: (i64, f64)) -> Point {
+    moved(point, by)
+}
+
+#[remote_fn(format = "msgpack")]
+fn move_msgpack(point: Point, by: (i64, f64)) -> Point {
+    moved(point, by)
+}
+
+/// Checks that the last call serialized its inputs and output in `format`.
+fn check_last(backend: &RecordingBackend, format: Format) {
+    let calls = backend.calls();
+    let call = calls.last().expect("no calls");
+    let input: Point = format.deserialize(&call.inputs[0]).unwrap();
+    assert_eq!(input, point(), "input in {}", format);
+    let input: (i64, f64) = format.deserialize(&call.inputs[1]).unwrap();
+    assert_eq!(input, (1, 1.5), "input in {}", format);
+    let output: Point = format.deserialize(call.output.as_ref().unwrap()).unwrap();
+    assert_eq!(output, moved(point(), (1, 1.5)), "output in {}", format);
+}
+
+#[actix_rt::test]
+async fn round_trip() {
+    let backend = RecordingBackend::default();
+    gfaas::set_thread_backend(backend.clone());
+    let expected = moved(point(), (1, 1.5));
+
+    assert_eq!(move_json(point(), (1, 1.5)).await.unwrap(), expected);
+    check_last(&backend, Format::Json);
+    assert_eq!(move_bincode(point(), (1, 1.5)).await.unwrap(), expected);
+    check_last(&backend, Format::Bincode);
+    assert_eq!(move_cbor(point(), (1, 1.5)).await.unwrap(), expected);
+    check_last(&backend, Format::Cbor);
+    assert_eq!(move_msgpack(point(), (1, 1.5)).await.unwrap(), expected);
+    check_last(&backend, Format::Msgpack);
+}
+
+#[test]
+fn formats() {
+    let formats = [Format::Json, Format::Bincode, Format::Cbor, Format::Msgpack];
+    let serialized: Vec<_> = formats
+        .iter()
+        .map(|format| format.serialize(&point()).unwrap())
+        .collect();
+    for (format, data) in formats.iter().zip(&serialized) {
+        assert_eq!(format.deserialize::<Point>(data).unwrap(), point());
+    }
+    // The formats are distinct on the wire.
+    for (i, data) in serialized.iter().enumerate() {
+        assert!(serialized[i + 1..].iter().all(|other| other != data));
+    }
+    assert!(Format::Json.deserialize::<Point>(&serialized[1]).is_err());
+    assert_eq!(Format::default(), Format::Json);
+}
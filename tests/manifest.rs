@@ -0,0 +1,125 @@
+use gfaas::{EntryPoint, Manifest, ManifestBuilder, MountPoint, Package};
+use std::fs;
+use ya_runtime_wasi::DeployFile;
+
+fn deploy(package: Package, manifest: Option<&Manifest>) -> (tempfile::TempDir, DeployFile) {
+    let dir = tempfile::tempdir().unwrap();
+    let package_path = dir.path().join("package.zip");
+    let workdir = dir.path().join("workdir");
+    fs::create_dir(&workdir).unwrap();
+
+    match manifest {
+        Some(manifest) => package
+            .write_with_manifest(manifest, &package_path)
+            .unwrap(),
+        None => package.write(&package_path).unwrap(),
+    }
+    ya_runtime_wasi::deploy(&workdir, &package_path).unwrap();
+    let deploy_file = DeployFile::load(&workdir).unwrap();
+    assert_eq!(deploy_file.image_path(), package_path.as_path());
+
+    (dir, deploy_file)
+}
+
+fn vol_paths(deploy_file: &DeployFile) -> Vec<&str> {
+    deploy_file.vols().map(|vol| vol.path.as_str()).collect()
+}
+
+#[test]
+fn default_manifest() {
+    let mut package = Package::new();
+    package
+        .add_module_from_bytes("hello.wasm", b"\0asm")
+        .unwrap();
+
+    let (_dir, deploy_file) = deploy(package, None);
+    assert_eq!(vol_paths(&deploy_file), ["/workdir"]);
+}
+
+#[test]
+fn custom_manifest() {
+    let mut package = Package::new();
+    package
+        .add_module_from_bytes("hello.wasm", b"\0asm")
+        .unwrap();
+    package
+        .add_module_from_bytes("goodbye.wasm", b"\0asm")
+        .unwrap();
+    let manifest = ManifestBuilder::new("greetings", "Greetings")
+        .with_version("1.2.3")
+        .with_entry_point(
+            EntryPoint::new("hello", "hello.wasm")
+                .with_arg("--loud")
+                .with_env("LANG", "en"),
+        )
+        .with_entry_point(EntryPoint::new("goodbye", "goodbye.wasm"))
+        .with_mount_point(MountPoint::Ro("input".to_owned()))
+        .with_mount_point(MountPoint::Rw("/workdir".to_owned()))
+        .with_mount_point(MountPoint::Wo("output".to_owned()))
+        .build();
+
+    let (_dir, deploy_file) = deploy(package, Some(&manifest));
+    assert_eq!(vol_paths(&deploy_file), ["/input", "/workdir", "/output"]);
+}
+
+#[test]
+fn manifest_json() {
+    let manifest = ManifestBuilder::new("greetings", "Greetings")
+        .with_version("1.2.3")
+        .with_entry_point(
+            EntryPoint::new("hello", "hello.wasm")
+                .with_arg("--loud")
+                .with_env("LANG", "en"),
+        )
+        .with_entry_point(EntryPoint::new("goodbye", "goodbye.wasm"))
+        .with_mount_point(MountPoint::Ro("input".to_owned()))
+        .with_mount_point(MountPoint::Wo("output".to_owned()))
+        .build();
+
+    assert_eq!(
+        manifest.to_json(),
+        serde_json::json!({
+            "id": "greetings",
+            "name": "Greetings",
+            "version": "1.2.3",
+            "entry-points": [
+                {
+                    "id": "hello",
+                    "wasm-path": "hello.wasm",
+                    "args": ["--loud"],
+                    "env": { "LANG": "en" },
+                },
+                {
+                    "id": "goodbye",
+                    "wasm-path": "goodbye.wasm",
+                },
+            ],
+            "mount-points": [
+                { "ro": "input" },
+                { "wo": "output" },
+            ],
+        })
+    );
+}
+
+#[test]
+fn missing_module() {
+    let mut package = Package::new();
+    package
+        .add_module_from_bytes("hello.wasm", b"\0asm")
+        .unwrap();
+    let manifest = ManifestBuilder::new("greetings", "Greetings")
+        .with_entry_point(EntryPoint::new("goodbye", "goodbye.wasm"))
+        .build();
+
+    let dir = tempfile::tempdir().unwrap();
+    let package_path = dir.path().join("package.zip");
+    let err = package
+        .write_with_manifest(&manifest, &package_path)
+        .unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "entry point 'goodbye' refers to Wasm module 'goodbye.wasm' missing from the package"
+    );
+    assert!(!package_path.exists());
+}
@@ -0,0 +1,131 @@
+use gfaas::{
+    remote_fn,
+    testing::{Fault, MockBackend},
+    Error,
+};
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, Instant};
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct Stats {
+    count: usize,
+    sum: u64,
+}
+
+#[remote_fn]
+fn stats(name: &str, values: &[u64]) -> (String, Stats) {
+    let stats = Stats {
+        count: values.len(),
+        sum: values.iter().sum(),
+    };
+    (name.to_uppercase(), stats)
+}
+
+#[remote_fn(timeout = 1)]
+fn double(x: u64) -> u64 {
+    x * 2
+}
+
+#[actix_rt::test]
+async fn round_trip() {
+    gfaas::set_thread_backend(MockBackend::new());
+
+    let (name, stats) = stats("values", &[1, 2, 3]).await.unwrap();
+    assert_eq!(name, "VALUES");
+    assert_eq!(stats, Stats { count: 3, sum: 6 });
+}
+
+#[actix_rt::test]
+async fn error_fault() {
+    let mock = MockBackend::new().with_fault(0, Fault::Error("provider crashed".to_owned()));
+    gfaas::set_thread_backend(mock.clone());
+
+    match double(1).await {
+        Err(Error::Run(err)) => assert_eq!(err.to_string(), "provider crashed"),
+        res => panic!("unexpected result: {:?}", res),
+    }
+    // Faults affect only the call they were injected into.
+    assert_eq!(double(1).await.unwrap(), 2);
+}
+
+#[actix_rt::test]
+async fn delay_fault() {
+    let mock = MockBackend::new().with_fault(0, Fault::Delay(Duration::from_millis(100)));
+    gfaas::set_thread_backend(mock.clone());
+
+    let start = Instant::now();
+    assert_eq!(double(2).await.unwrap(), 4);
+    assert!(start.elapsed() >= Duration::from_millis(100));
+
+    // Delays exceeding the timeout time the call out once the timeout elapses.
+    let mock = MockBackend::new().with_fault(0, Fault::Delay(Duration::from_secs(60)));
+    gfaas::set_thread_backend(mock.clone());
+
+    let start = Instant::now();
+    match double(2).await {
+        Err(Error::Timeout(timeout)) => assert_eq!(timeout, Duration::from_secs(1)),
+        res => panic!("unexpected result: {:?}", res),
+    }
+    let elapsed = start.elapsed();
+    assert!(elapsed >= Duration::from_secs(1) && elapsed < Duration::from_secs(60));
+}
+
+#[actix_rt::test]
+async fn delay() {
+    let mock = MockBackend::new()
+        .with_delay(Duration::from_millis(50))
+        .with_fault(1, Fault::Delay(Duration::from_millis(0)));
+    gfaas::set_thread_backend(mock.clone());
+
+    let start = Instant::now();
+    assert_eq!(double(3).await.unwrap(), 6);
+    assert!(start.elapsed() >= Duration::from_millis(50));
+    // Delay fault overrides the delay of every call.
+    let start = Instant::now();
+    assert_eq!(double(3).await.unwrap(), 6);
+    assert!(start.elapsed() < Duration::from_millis(50));
+}
+
+#[actix_rt::test]
+async fn timeout_fault() {
+    let mock = MockBackend::new().with_fault(0, Fault::Timeout);
+    gfaas::set_thread_backend(mock.clone());
+
+    let start = Instant::now();
+    match double(4).await {
+        Err(Error::Timeout(timeout)) => assert_eq!(timeout, Duration::from_secs(1)),
+        res => panic!("unexpected result: {:?}", res),
+    }
+    // The call times out immediately rather than after the timeout.
+    assert!(start.elapsed() < Duration::from_secs(1));
+}
+
+#[actix_rt::test]
+async fn calls() {
+    let mock = MockBackend::new().with_fault(1, Fault::Error("provider crashed".to_owned()));
+    gfaas::set_thread_backend(mock.clone());
+    assert_eq!(mock.calls(), 0);
+
+    double(1).await.unwrap();
+    assert_eq!(mock.calls(), 1);
+    // Failed calls are counted too.
+    assert!(double(1).await.is_err());
+    assert_eq!(mock.calls(), 2);
+    // As are the calls of a batch, each of its invocations separately.
+    double::batch(vec![1, 2, 3]).await.unwrap();
+    assert_eq!(mock.calls(), 5);
+
+    // Backends installed on other threads have their own counts.
+    let other = MockBackend::new();
+    std::thread::spawn({
+        let other = other.clone();
+        move || {
+            gfaas::set_thread_backend(other);
+            actix_rt::System::new("other").block_on(double(1)).unwrap();
+        }
+    })
+    .join()
+    .unwrap();
+    assert_eq!(other.calls(), 1);
+    assert_eq!(mock.calls(), 5);
+}
@@ -0,0 +1,296 @@
+use gfaas::{
+    Compression, EntryPoint, Invocation, ManifestBuilder, MountPoint, Package, PackageCache,
+};
+use std::{fs, io::Write, path::Path};
+use zip::{write::FileOptions, CompressionMethod, ZipArchive, ZipWriter};
+
+/// Smallest valid Wasm module.
+const EMPTY_MODULE: &[u8] = b"\0asm\x01\0\0\0";
+
+fn package(module_name: &str, contents: &[u8]) -> Package {
+    let mut package = Package::new();
+    package
+        .add_module_from_bytes(module_name, contents)
+        .unwrap();
+    package
+}
+
+/// Valid Wasm module of `size` bytes or so, padded with a custom section of zeros.
+fn large_module(size: usize) -> Vec<u8> {
+    let mut module = EMPTY_MODULE.to_vec();
+    module.push(0);
+    // Section size as LEB128, covering the name of the section and the padding.
+    let mut section_size = size + 2;
+    loop {
+        let byte = (section_size & 0x7f) as u8;
+        section_size >>= 7;
+        if section_size == 0 {
+            module.push(byte);
+            break;
+        }
+        module.push(byte | 0x80);
+    }
+    module.extend_from_slice(&[1, b'x']);
+    module.resize(module.len() + size, 0);
+    module
+}
+
+fn write_zip(path: &Path, files: &[(&str, &[u8])]) {
+    let mut zip_writer = ZipWriter::new(fs::File::create(path).unwrap());
+    for (name, contents) in files {
+        zip_writer
+            .start_file(*name, FileOptions::default())
+            .unwrap();
+        zip_writer.write_all(contents).unwrap();
+    }
+    zip_writer.finish().unwrap();
+}
+
+fn open_err(files: &[(&str, &[u8])]) -> String {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("package.zip");
+    write_zip(&path, files);
+    format!("{:#}", Package::open(&path).err().unwrap())
+}
+
+#[test]
+fn open_default() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("package.zip");
+    let mut package = Package::new();
+    package
+        .add_module_from_bytes("hello.wasm", EMPTY_MODULE)
+        .unwrap();
+    let manifest = package.manifest();
+    package.write(&path).unwrap();
+
+    let package = Package::open(&path).unwrap();
+    assert_eq!(package.manifest(), manifest);
+    assert_eq!(package.manifest().id(), "custom");
+    assert_eq!(
+        package.manifest().mount_points(),
+        [MountPoint::Rw("workdir".to_owned())]
+    );
+    assert_eq!(package.module_names().collect::<Vec<_>>(), ["hello.wasm"]);
+    assert_eq!(package.module("hello.wasm"), Some(EMPTY_MODULE));
+}
+
+#[test]
+fn open_custom() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("package.zip");
+    let mut package = Package::new();
+    package
+        .add_module_from_bytes("hello.wasm", EMPTY_MODULE)
+        .unwrap();
+    package
+        .add_module_from_bytes("goodbye.wasm", EMPTY_MODULE)
+        .unwrap();
+    let manifest = ManifestBuilder::new("greetings", "Greetings")
+        .with_version("1.2.3")
+        .with_entry_point(
+            EntryPoint::new("hello", "hello.wasm")
+                .with_arg("--loud")
+                .with_env("LANG", "en"),
+        )
+        .with_entry_point(EntryPoint::new("bye", "goodbye.wasm"))
+        .with_mount_point(MountPoint::Ro("input".to_owned()))
+        .with_mount_point(MountPoint::Wo("output".to_owned()))
+        .build();
+    package.write_with_manifest(&manifest, &path).unwrap();
+
+    let package = Package::open(&path).unwrap();
+    assert_eq!(package.manifest(), manifest);
+    assert_eq!(package.module("goodbye.wasm"), Some(EMPTY_MODULE));
+    assert_eq!(package.module("bye.wasm"), None);
+
+    // Written again, the opened package keeps its manifest.
+    let copy_path = dir.path().join("copy.zip");
+    package.write(&copy_path).unwrap();
+    assert_eq!(Package::open(&copy_path).unwrap().manifest(), manifest);
+}
+
+#[test]
+fn open_invalid() {
+    let manifest = br#"{
+        "id": "custom",
+        "name": "custom",
+        "entry-points": [{ "id": "hello", "wasm-path": "hello.wasm" }],
+        "mount-points": [{ "rw": "workdir" }]
+    }"#;
+
+    assert_eq!(
+        open_err(&[("hello.wasm", EMPTY_MODULE)]),
+        "package contains no manifest"
+    );
+    assert_eq!(
+        open_err(&[("manifest.json", manifest)]),
+        "entry point 'hello' refers to Wasm module 'hello.wasm' missing from the package"
+    );
+    assert!(
+        open_err(&[("hello.wasm", b"\0asm"), ("manifest.json", manifest)])
+            .starts_with("entry point 'hello' refers to invalid Wasm module 'hello.wasm': ")
+    );
+    assert_eq!(
+        open_err(&[
+            ("hello.wasm", EMPTY_MODULE),
+            ("manifest.json", br#"{ "id": "custom" }"#)
+        ]),
+        "parsing manifest: 'name' is missing or is not a string"
+    );
+    assert_eq!(
+        open_err(&[
+            ("hello.wasm", EMPTY_MODULE),
+            (
+                "manifest.json",
+                br#"{ "id": "custom", "name": "custom", "mount-points": [{ "private": "tmp" }] }"#
+            )
+        ]),
+        "parsing manifest: unknown mount point 'private': expected 'ro', 'rw' or 'wo'"
+    );
+}
+
+#[test]
+fn cache() {
+    let dir = tempfile::tempdir().unwrap();
+    let cache = PackageCache::new(dir.path().join("packages"));
+
+    let hello = cache.insert(package("hello.wasm", EMPTY_MODULE)).unwrap();
+    assert_eq!(
+        hello.path(),
+        cache.dir().join(format!("{}.zip", hello.hash()))
+    );
+    assert_eq!(hello.hash(), package("hello.wasm", EMPTY_MODULE).hash());
+    assert_eq!(Package::open(hello.path()).unwrap().hash(), hello.hash());
+
+    // Cached packages are reused, by other caches of the same dir too.
+    let modified = fs::metadata(hello.path()).unwrap().modified().unwrap();
+    let cache = PackageCache::new(cache.dir());
+    assert_eq!(cache.get(hello.hash()), Some(hello.path().to_owned()));
+    assert_eq!(
+        cache.insert(package("hello.wasm", EMPTY_MODULE)).unwrap(),
+        hello
+    );
+    assert_eq!(
+        fs::metadata(hello.path()).unwrap().modified().unwrap(),
+        modified
+    );
+
+    // Packages differing in any module are cached separately.
+    let goodbye = cache.insert(package("goodbye.wasm", EMPTY_MODULE)).unwrap();
+    assert_ne!(goodbye.hash(), hello.hash());
+    let other = cache.insert(package("hello.wasm", b"\0asm")).unwrap();
+    assert_ne!(other.hash(), hello.hash());
+    assert_eq!(cache.get("missing"), None);
+
+    // Corrupted or tampered packages aren't trusted by their name, and are replaced.
+    fs::write(hello.path(), b"garbage").unwrap();
+    assert_eq!(cache.get(hello.hash()), None);
+    fs::copy(goodbye.path(), hello.path()).unwrap();
+    assert_eq!(cache.get(hello.hash()), None);
+    assert_eq!(
+        cache.insert(package("hello.wasm", EMPTY_MODULE)).unwrap(),
+        hello
+    );
+    assert_eq!(cache.get(hello.hash()), Some(hello.path().to_owned()));
+    assert_eq!(Package::open(hello.path()).unwrap().hash(), hello.hash());
+}
+
+#[test]
+fn invocation_cache() {
+    let dir = tempfile::tempdir().unwrap();
+    let invocation = Invocation::new("hello")
+        .with_module(EMPTY_MODULE)
+        .with_datadir(dir.path());
+    assert_eq!(
+        invocation.package_cache().dir(),
+        dir.path().join("packages")
+    );
+
+    let cached = invocation.cached_package().unwrap();
+    assert_eq!(cached.hash(), package("hello.wasm", EMPTY_MODULE).hash());
+    assert_eq!(invocation.cached_package().unwrap(), cached);
+}
+
+#[test]
+fn reproducible() {
+    let dir = tempfile::tempdir().unwrap();
+    let write = |module_names: &[&str], name: &str| {
+        let mut package = Package::new();
+        for module_name in module_names {
+            package
+                .add_module_from_bytes(*module_name, EMPTY_MODULE)
+                .unwrap();
+        }
+        let path = dir.path().join(name);
+        package.write(&path).unwrap();
+        fs::read(path).unwrap()
+    };
+
+    let package = write(&["hello.wasm", "goodbye.wasm"], "first.zip");
+    assert_eq!(
+        write(&["goodbye.wasm", "hello.wasm"], "second.zip"),
+        package
+    );
+
+    let mut archive =
+        ZipArchive::new(fs::File::open(dir.path().join("first.zip")).unwrap()).unwrap();
+    let mut names = vec![];
+    for i in 0..archive.len() {
+        let file = archive.by_index(i).unwrap();
+        names.push(file.name().to_owned());
+        let modified = file.last_modified();
+        assert_eq!(
+            (modified.year(), modified.month(), modified.day()),
+            (1980, 1, 1)
+        );
+        assert_eq!(
+            (modified.hour(), modified.minute(), modified.second()),
+            (0, 0, 0)
+        );
+        assert_eq!(file.unix_mode(), Some(0o100644));
+    }
+    assert_eq!(names, ["goodbye.wasm", "hello.wasm", "manifest.json"]);
+}
+
+#[test]
+fn compression() {
+    let dir = tempfile::tempdir().unwrap();
+    let module = large_module(100_000);
+    let write = |compression, name: &str| {
+        let mut package = package("hello.wasm", &module);
+        package.set_compression(compression);
+        let hash = package.hash();
+        let path = dir.path().join(name);
+        package.write(&path).unwrap();
+        (hash, path)
+    };
+
+    let (stored_hash, stored_path) = write(Compression::Stored, "stored.zip");
+    let (deflate_hash, deflate_path) = write(Compression::Deflate, "deflate.zip");
+    assert_ne!(stored_hash, deflate_hash);
+    let stored_len = fs::metadata(&stored_path).unwrap().len();
+    let deflate_len = fs::metadata(&deflate_path).unwrap().len();
+    assert!(stored_len > 100_000);
+    assert!(deflate_len < 1_000);
+    let (_, again_path) = write(Compression::Deflate, "again.zip");
+    assert_eq!(
+        fs::read(again_path).unwrap(),
+        fs::read(&deflate_path).unwrap()
+    );
+
+    let mut archive = ZipArchive::new(fs::File::open(&deflate_path).unwrap()).unwrap();
+    assert_eq!(
+        archive.by_index(0).unwrap().compression(),
+        CompressionMethod::Deflated
+    );
+
+    let package = Package::open(&deflate_path).unwrap();
+    assert_eq!(package.compression(), Compression::Deflate);
+    assert_eq!(package.module("hello.wasm"), Some(module.as_slice()));
+    assert_eq!(package.hash(), deflate_hash);
+    assert_eq!(
+        Package::open(&stored_path).unwrap().compression(),
+        Compression::Stored
+    );
+}
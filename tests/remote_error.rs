@@ -0,0 +1,101 @@
+use gfaas::{
+    remote_fn,
+    testing::{Fault, MockBackend},
+    Error, RemoteError,
+};
+use serde::{Deserialize, Serialize};
+use std::{error::Error as _, fmt};
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+enum ParseError {
+    Empty,
+    NotANumber(String),
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Empty => write!(f, "empty input"),
+            Self::NotANumber(input) => write!(f, "'{}' is not a number", input),
+        }
+    }
+}
+
+#[remote_fn(retries = 2, backoff = 0)]
+fn parse(input: &str) -> Result<u64, ParseError> {
+    if input.is_empty() {
+        return Err(ParseError::Empty);
+    }
+    input
+        .parse()
+        .map_err(|_| ParseError::NotANumber(input.to_owned()))
+}
+
+#[actix_rt::test]
+async fn application() {
+    // Errors returned by the function are not retried.
+    let mock = MockBackend::new();
+    gfaas::set_thread_backend(mock.clone());
+
+    assert_eq!(parse("12").await.unwrap(), 12);
+    match parse("").await {
+        Err(RemoteError::Application(ParseError::Empty)) => {}
+        res => panic!("unexpected result: {:?}", res),
+    }
+    let err = parse("x").await.unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "remote function returned an error: 'x' is not a number"
+    );
+    assert!(err.source().is_none());
+    assert_eq!(
+        err.into_application(),
+        Some(ParseError::NotANumber("x".to_owned()))
+    );
+    assert_eq!(mock.calls(), 3);
+}
+
+#[actix_rt::test]
+async fn infrastructure() {
+    let mock = MockBackend::new()
+        .with_fault(0, Fault::Error("provider crashed".to_owned()))
+        .with_fault(1, Fault::Error("provider crashed".to_owned()))
+        .with_fault(2, Fault::Error("provider crashed again".to_owned()))
+        .with_fault(3, Fault::Timeout)
+        .with_fault(4, Fault::Timeout)
+        .with_fault(5, Fault::Timeout);
+    gfaas::set_thread_backend(mock.clone());
+
+    let err = parse("12").await.unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "running remote function failed: running Wasm module"
+    );
+    assert!(matches!(
+        err.source().and_then(|err| err.downcast_ref()),
+        Some(Error::Run(_))
+    ));
+    match err {
+        RemoteError::Infrastructure(Error::Run(err)) => {
+            assert_eq!(err.to_string(), "provider crashed again")
+        }
+        err => panic!("unexpected error: {:?}", err),
+    }
+
+    match parse("12").await {
+        Err(err @ RemoteError::Infrastructure(Error::Timeout(_))) => {
+            assert_eq!(err.into_application(), None)
+        }
+        res => panic!("unexpected result: {:?}", res),
+    }
+    assert_eq!(mock.calls(), 6);
+
+    // Recovers once the faults are gone.
+    assert_eq!(parse("12").await.unwrap(), 12);
+}
+
+#[test]
+fn from_error() {
+    let err: RemoteError<ParseError> = Error::Run(anyhow::anyhow!("provider crashed")).into();
+    assert!(matches!(err, RemoteError::Infrastructure(Error::Run(_))));
+}
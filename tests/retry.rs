@@ -0,0 +1,64 @@
+use gfaas::{
+    remote_fn,
+    testing::{Fault, MockBackend},
+    Error,
+};
+
+#[remote_fn(retries = 2, backoff = 0)]
+fn increment(x: u64) -> u64 {
+    x + 1
+}
+
+#[actix_rt::test]
+async fn transient() {
+    let mock = MockBackend::new()
+        .with_fault(0, Fault::Error("provider crashed".to_owned()))
+        .with_fault(1, Fault::Timeout);
+    gfaas::set_thread_backend(mock.clone());
+
+    assert_eq!(increment(1).await.unwrap(), 2);
+    assert_eq!(mock.calls(), 3);
+}
+
+#[actix_rt::test]
+async fn retries_exhausted() {
+    let mock = MockBackend::new()
+        .with_fault(0, Fault::Error("provider crashed".to_owned()))
+        .with_fault(1, Fault::Error("provider crashed".to_owned()))
+        .with_fault(2, Fault::Error("provider crashed again".to_owned()));
+    gfaas::set_thread_backend(mock.clone());
+
+    match increment(1).await {
+        Err(Error::Run(err)) => assert_eq!(err.to_string(), "provider crashed again"),
+        res => panic!("unexpected result: {:?}", res),
+    }
+    assert_eq!(mock.calls(), 3);
+}
+
+#[actix_rt::test]
+async fn non_transient() {
+    let mock = MockBackend::new().with_fault(0, Fault::Backend("app key rejected".to_owned()));
+    gfaas::set_thread_backend(mock.clone());
+
+    match increment(1).await {
+        Err(err @ Error::Backend(_)) => assert!(!err.is_transient()),
+        res => panic!("unexpected result: {:?}", res),
+    }
+    assert_eq!(mock.calls(), 1);
+}
+
+#[actix_rt::test]
+async fn batch() {
+    // The whole batch is retried if any of its invocations fails.
+    let mock = MockBackend::new().with_fault(1, Fault::Timeout);
+    gfaas::set_thread_backend(mock.clone());
+
+    assert_eq!(increment::batch(vec![1, 2]).await.unwrap(), [2, 3]);
+    assert_eq!(mock.calls(), 4);
+
+    let mock = MockBackend::new().with_fault(1, Fault::Backend("app key rejected".to_owned()));
+    gfaas::set_thread_backend(mock.clone());
+
+    assert!(increment::batch(vec![1, 2]).await.is_err());
+    assert_eq!(mock.calls(), 2);
+}
@@ -0,0 +1,130 @@
+use futures::{
+    future::LocalBoxFuture,
+    stream::{LocalBoxStream, StreamExt, TryStreamExt},
+};
+use gfaas::{
+    remote_fn,
+    testing::{Fault, MockBackend},
+    Backend, Completed, Error, Invocation, RemoteError,
+};
+use std::time::Duration;
+
+#[remote_fn]
+fn square(x: u64) -> u64 {
+    x * x
+}
+
+#[remote_fn]
+fn parse(input: &str) -> Result<u64, String> {
+    input
+        .parse()
+        .map_err(|_| format!("'{}' is not a number", input))
+}
+
+/// Backend running invocations with a mock, as if each of them ran in an activity of its own.
+#[derive(Clone, Default)]
+struct Activities {
+    mock: MockBackend,
+}
+
+impl Backend for Activities {
+    fn run<'a>(&'a self, invocation: Invocation) -> LocalBoxFuture<'a, Result<Vec<u8>, Error>> {
+        self.mock.run(invocation)
+    }
+
+    fn run_streaming<'a>(
+        &'a self,
+        invocations: Vec<Invocation>,
+    ) -> LocalBoxStream<'a, Result<Completed<Vec<u8>>, Error>> {
+        self.mock
+            .run_streaming(invocations)
+            .map_ok(|completed| Completed {
+                activity_id: Some(format!("activity-{}", completed.index)),
+                ..completed
+            })
+            .boxed_local()
+    }
+}
+
+#[actix_rt::test]
+async fn completion_order() {
+    // Earlier invocations complete later.
+    let mock = MockBackend::new()
+        .with_fault(0, Fault::Delay(Duration::from_millis(100)))
+        .with_fault(1, Fault::Delay(Duration::from_millis(50)));
+    gfaas::set_thread_backend(mock.clone());
+
+    let outputs: Vec<_> = square::stream(vec![1, 2, 3]).try_collect().await.unwrap();
+    assert_eq!(
+        outputs,
+        [
+            Completed {
+                index: 2,
+                activity_id: None,
+                output: 9
+            },
+            Completed {
+                index: 1,
+                activity_id: None,
+                output: 4
+            },
+            Completed {
+                index: 0,
+                activity_id: None,
+                output: 1
+            },
+        ]
+    );
+    assert_eq!(mock.calls(), 3);
+}
+
+#[actix_rt::test]
+async fn activity_ids() {
+    gfaas::set_thread_backend(Activities::default());
+
+    let mut outputs: Vec<_> = square::stream(vec![1, 2, 3]).try_collect().await.unwrap();
+    outputs.sort_by_key(|completed| completed.index);
+    for (index, completed) in outputs.into_iter().enumerate() {
+        assert_eq!(completed.index, index);
+        assert_eq!(completed.activity_id, Some(format!("activity-{}", index)));
+        assert_eq!(completed.output, (index as u64 + 1).pow(2));
+    }
+}
+
+#[actix_rt::test]
+async fn failure() {
+    // Unlike with `batch`, outputs of the other invocations are still yielded.
+    let mock = MockBackend::new().with_fault(1, Fault::Error("provider crashed".to_owned()));
+    gfaas::set_thread_backend(mock.clone());
+
+    let outputs: Vec<_> = square::stream(vec![1, 2, 3]).collect().await;
+    let mut completed = Vec::new();
+    for output in outputs {
+        match output {
+            Ok(output) => completed.push((output.index, output.output)),
+            Err(Error::Run(err)) => assert_eq!(err.to_string(), "provider crashed"),
+            Err(err) => panic!("unexpected error: {:?}", err),
+        }
+    }
+    completed.sort();
+    assert_eq!(completed, [(0, 1), (2, 9)]);
+    assert_eq!(mock.calls(), 3);
+}
+
+#[actix_rt::test]
+async fn application_errors() {
+    gfaas::set_thread_backend(MockBackend::new());
+
+    let mut outputs: Vec<_> = parse::stream(vec!["1".to_owned(), "x".to_owned()])
+        .try_collect()
+        .await
+        .unwrap();
+    outputs.sort_by_key(|completed| completed.index);
+    match &outputs[..] {
+        [Completed { output: Ok(1), .. }, Completed {
+            output: Err(RemoteError::Application(err)),
+            ..
+        }] => assert_eq!(err, "'x' is not a number"),
+        outputs => panic!("unexpected outputs: {:?}", outputs),
+    }
+}
@@ -0,0 +1,8 @@
+use gfaas::remote_fn;
+
+#[remote_fn(budget = 10, budget = 20)]
+fn hello(input: String) -> String {
+    input
+}
+
+fn main() {}
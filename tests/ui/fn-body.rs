@@ -0,0 +1,8 @@
+use gfaas::remote_fn;
+
+fn main() {
+    #[remote_fn]
+    fn hello(input: String) -> String {
+        input
+    }
+}
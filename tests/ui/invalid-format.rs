@@ -0,0 +1,8 @@
+use gfaas::remote_fn;
+
+#[remote_fn(format = "yaml")]
+fn hello(input: String) -> String {
+    input
+}
+
+fn main() {}
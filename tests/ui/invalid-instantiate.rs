@@ -0,0 +1,8 @@
+use gfaas::remote_fn;
+
+#[remote_fn(instantiate(T = u64, U = f64))]
+fn identity<T: serde::Serialize>(value: T) -> T {
+    value
+}
+
+fn main() {}
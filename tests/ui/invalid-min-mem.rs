@@ -0,0 +1,8 @@
+use gfaas::remote_fn;
+
+#[remote_fn(min_mem_gib = -1.5)]
+fn hello(input: String) -> String {
+    input
+}
+
+fn main() {}
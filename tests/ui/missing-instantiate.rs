@@ -0,0 +1,8 @@
+use gfaas::remote_fn;
+
+#[remote_fn]
+fn identity<T: serde::Serialize>(value: T) -> T {
+    value
+}
+
+fn main() {}
@@ -0,0 +1,9 @@
+use gfaas::remote_fn;
+
+#[remote_fn]
+fn push(values: &mut Vec<u64>) -> usize {
+    values.push(1);
+    values.len()
+}
+
+fn main() {}
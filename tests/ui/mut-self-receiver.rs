@@ -0,0 +1,14 @@
+use gfaas::remote_fn;
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct Counter(u64);
+
+#[remote_fn]
+impl Counter {
+    #[remote_fn]
+    fn increment(&mut self) {
+        self.0 += 1;
+    }
+}
+
+fn main() {}
@@ -0,0 +1,8 @@
+use gfaas::remote_fn;
+
+#[remote_fn(constraints = "golem.runtime.name == wasmtime, golem.inf.cpu.threads < inf")]
+fn hello(input: String) -> String {
+    input
+}
+
+fn main() {}
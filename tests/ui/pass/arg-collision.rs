@@ -0,0 +1,11 @@
+use gfaas::remote_fn;
+
+#[remote_fn]
+fn sum((a, b): (u8, u8), arg0: u8) -> u8 {
+    a + b + arg0
+}
+
+fn main() {
+    let _: &dyn std::future::Future<Output = Result<u8, gfaas::Error>> = &sum((1, 2), 3);
+    let _ = sum::batch(vec![((1, 2), 3)]);
+}
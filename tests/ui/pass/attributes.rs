@@ -0,0 +1,25 @@
+//! Attributes of annotated functions are carried over to the expanded ones.
+#![deny(missing_docs)]
+
+use gfaas::remote_fn;
+
+/// Adds up the values.
+#[remote_fn(instantiate(T = u64))]
+#[inline]
+pub fn total<T>(values: Vec<T>) -> T
+where
+    T: std::iter::Sum + serde::Serialize + serde::de::DeserializeOwned,
+{
+    values.into_iter().sum()
+}
+
+/// Never compiled at all.
+#[remote_fn]
+#[cfg(any())]
+pub fn missing(input: Missing) -> Missing {
+    input
+}
+
+fn main() {
+    let _: &dyn std::future::Future<Output = Result<u64, gfaas::Error>> = &total(vec![1u64, 2]);
+}
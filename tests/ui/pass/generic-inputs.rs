@@ -0,0 +1,15 @@
+use gfaas::remote_fn;
+use serde::{de::DeserializeOwned, Serialize};
+
+// `batch` and `stream` are generic over their inputs too.
+#[remote_fn(instantiate(I = u64))]
+fn first<I: Serialize + DeserializeOwned + Copy>(items: Vec<I>) -> Option<I> {
+    items.first().copied()
+}
+
+fn main() {
+    let _: &dyn std::future::Future<Output = Result<Option<u64>, gfaas::Error>> =
+        &first(vec![1u64, 2]);
+    let _ = first::batch(vec![vec![1u64], vec![2, 3]]);
+    let _ = first::stream(vec![vec![1u64]]);
+}
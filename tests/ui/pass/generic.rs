@@ -0,0 +1,22 @@
+use gfaas::remote_fn;
+use serde::{de::DeserializeOwned, Serialize};
+
+#[remote_fn(instantiate(T = u64, T = f64))]
+fn total<T: Serialize + DeserializeOwned + std::iter::Sum>(values: Vec<T>) -> T {
+    values.into_iter().sum()
+}
+
+#[remote_fn(instantiate((K = String, V = u64), (K = u64, V = Vec<u8>)))]
+fn entry<K: Serialize + DeserializeOwned, V: Serialize + DeserializeOwned>(
+    key: K,
+    value: V,
+) -> (K, V) {
+    (key, value)
+}
+
+fn main() {
+    let _: &dyn std::future::Future<Output = Result<u64, gfaas::Error>> = &total(vec![1u64, 2]);
+    let _: &dyn std::future::Future<Output = Result<f64, gfaas::Error>> = &total(vec![1.0f64]);
+    let _ = total::batch(vec![vec![1u64], vec![2, 3]]);
+    let _ = entry("key".to_string(), 1u64);
+}
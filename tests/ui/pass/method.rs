@@ -0,0 +1,44 @@
+use gfaas::{remote_fn, shared};
+
+#[shared]
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct Rect {
+    width: f64,
+    height: f64,
+}
+
+#[remote_fn]
+impl Rect {
+    #[remote_fn]
+    pub fn new(width: f64, height: f64) -> Self {
+        Rect { width, height }
+    }
+
+    #[remote_fn(format = "bincode")]
+    pub fn area(&self, scale: f64) -> f64 {
+        self.width * self.height * scale
+    }
+
+    #[gfaas::remote_fn]
+    fn merge(self, other: &Self) -> Self {
+        Rect {
+            width: self.width.max(other.width),
+            height: self.height + other.height,
+        }
+    }
+}
+
+fn main() {
+    let _: &dyn std::future::Future<Output = Result<Rect, gfaas::Error>> = &Rect::new(1.0, 2.0);
+    let rect = Rect {
+        width: 1.0,
+        height: 2.0,
+    };
+    {
+        let _: &dyn std::future::Future<Output = Result<f64, gfaas::Error>> = &rect.area(2.0);
+    }
+    let _ = rect.merge(&Rect {
+        width: 2.0,
+        height: 1.0,
+    });
+}
@@ -0,0 +1,15 @@
+use gfaas::remote_fn;
+
+#[remote_fn]
+fn answer() -> u64 {
+    42
+}
+
+#[remote_fn]
+fn ping() {}
+
+fn main() {
+    let _: &dyn std::future::Future<Output = Result<u64, gfaas::Error>> = &answer();
+    let _: &dyn std::future::Future<Output = Result<(), gfaas::Error>> = &ping();
+    let _ = answer::batch(vec![(), ()]);
+}
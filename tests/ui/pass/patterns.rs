@@ -0,0 +1,13 @@
+use gfaas::remote_fn;
+
+#[remote_fn]
+fn describe(name: &str, (x, y): (i32, i32), values: &[u64], mut count: u64) -> String {
+    count += values.iter().sum::<u64>();
+    format!("{} at ({}, {}): {}", name, x, y, count)
+}
+
+fn main() {
+    let _: &dyn std::future::Future<Output = Result<String, gfaas::Error>> =
+        &describe("origin", (0, 0), &[1, 2, 3], 0);
+    let _ = describe::batch(vec![("origin".to_string(), (0, 0), vec![1, 2, 3], 0)]);
+}
@@ -0,0 +1,58 @@
+use gfaas::{remote_fn, shared};
+
+#[shared]
+use std::collections::HashMap;
+
+#[shared]
+const SCALE: u64 = 2;
+
+#[shared]
+fn scale(value: u64) -> u64 {
+    value * SCALE
+}
+
+#[shared]
+mod stats {
+    use super::*;
+
+    pub(crate) fn histogram(values: &[u64]) -> HashMap<u64, usize> {
+        let mut histogram = HashMap::new();
+        for value in values {
+            *histogram.entry(scale(*value)).or_default() += 1;
+        }
+        histogram
+    }
+}
+
+#[shared]
+#[derive(Default)]
+struct Counter {
+    count: usize,
+}
+
+#[shared]
+impl Counter {
+    fn bump(&mut self) {
+        self.count += 1;
+    }
+}
+
+#[shared]
+#[cfg(any())]
+fn configured_out() {}
+
+#[remote_fn]
+fn mode(values: Vec<u64>) -> Option<u64> {
+    let mut counter = Counter::default();
+    for _ in &values {
+        counter.bump();
+    }
+    stats::histogram(&values)
+        .into_iter()
+        .max_by_key(|(_, count)| *count)
+        .map(|(value, _)| value / SCALE)
+}
+
+fn main() {
+    let _: &dyn std::future::Future<Output = Result<Option<u64>, gfaas::Error>> = &mode(vec![]);
+}
@@ -0,0 +1,10 @@
+use gfaas::remote_fn;
+
+#[remote_fn]
+fn hello(input: String) {
+    println!("{}", input);
+}
+
+fn main() {
+    let _: &dyn std::future::Future<Output = Result<(), gfaas::Error>> = &hello(String::new());
+}
@@ -0,0 +1,12 @@
+use gfaas::remote_fn;
+
+struct Greeter;
+
+impl Greeter {
+    #[remote_fn]
+    fn hello(&self, input: String) -> String {
+        input
+    }
+}
+
+fn main() {}
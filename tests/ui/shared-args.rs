@@ -0,0 +1,6 @@
+use gfaas::shared;
+
+#[shared(pub)]
+fn helper() {}
+
+fn main() {}
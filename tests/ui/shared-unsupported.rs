@@ -0,0 +1,6 @@
+use gfaas::shared;
+
+#[shared]
+extern crate serde;
+
+fn main() {}
@@ -0,0 +1,8 @@
+use gfaas::remote_fn;
+
+#[remote_fn(constraints = "golem.node.id.name == \"alpha, beta")]
+fn hello(input: String) -> String {
+    input
+}
+
+fn main() {}